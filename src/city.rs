@@ -1,8 +1,277 @@
-use std::rand::{Rng, task_rng};
+use std::rand::{Rng, SeedableRng, XorShiftRng, task_rng};
+use std::fmt;
+use std::io;
+use std::mem::replace;
+use std::cmp::{min, max};
+use std::collections::HashMap;
+
+use rsfml::system::vector2::{Vector2f, Vector2i};
+use rsfml::graphics::Color;
 
 use map;
 use tile;
 
+
+pub static BOOKMARK_SLOTS: uint = 10;
+
+static AIRPORT_UPKEEP: f64 = 40.0;
+static AIRPORT_IMMIGRATION_BOOST: f64 = 2.0;
+
+//the commute in tiles that halves a workplace's chance to hire
+static COMMUTE_FALLOFF: f64 = 10.0;
+
+//the daily chance for an untouched grass tile to become forest, and how far development keeps it away
+static REGROWTH_CHANCE: f64 = 0.001;
+static REGROWTH_DISTANCE: int = 2;
+
+//the age in days where buildings start to decay, and the daily chance that they lose a level after that
+static DECAY_AGE: u32 = 1080;
+static DECAY_CHANCE: f64 = 0.001;
+
+//how much smaller the share of working residents is in a region where the homes have reached the decay age
+static AGING_WORKFORCE_LOSS: f64 = 0.3;
+
+//the extra growth and residential tax from homes next to water, halved for each tile further away
+static WATERFRONT_BONUS: f64 = 0.2;
+static WATERFRONT_DISTANCE: int = 2;
+
+static DEFAULT_TAX: f64 = 0.05;
+//how much the automatic balancing changes each tax per month, and how far it may go
+static AUTO_TAX_STEP: f64 = 0.005;
+static AUTO_TAX_MAX: f64 = 0.25;
+//the funds where the automatic balancing starts lowering the taxes again
+static FLUSH_FUNDS: f64 = 50_000.0;
+
+//the price of a good drifts randomly each day, while being pulled back towards the base price
+pub static BASE_GOODS_PRICE: f64 = 100.0;
+static GOODS_PRICE_VOLATILITY: f64 = 0.03;
+static GOODS_PRICE_REVERSION: f64 = 0.02;
+static MIN_GOODS_PRICE: f64 = 50.0;
+static MAX_GOODS_PRICE: f64 = 150.0;
+
+//how many goods per level an industry can keep in stock, and how many a store can keep beyond what it sells in a day
+static MAX_STORED_GOODS_PER_LEVEL: u32 = 16;
+static GOODS_BUFFER_PER_LEVEL: u32 = 2;
+
+#[deriving(Clone, PartialEq)]
+pub enum RandomEventKind {
+    Fire,
+    EconomicBoom,
+    Recession,
+    PopulationSurge
+}
+
+impl RandomEventKind {
+    //how many days the effect lasts
+    pub fn duration(&self) -> uint {
+        match *self {
+            Fire => 1,
+            EconomicBoom => 30,
+            Recession => 30,
+            PopulationSurge => 10
+        }
+    }
+}
+
+impl fmt::Show for RandomEventKind {
+    fn fmt(&self, buf: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Fire => write!(buf, "Fire"),
+            EconomicBoom => write!(buf, "Economic boom"),
+            Recession => write!(buf, "Recession"),
+            PopulationSurge => write!(buf, "Population surge")
+        }
+    }
+}
+
+//what a region can lack, as shown by the coverage overlay
+#[deriving(Clone, PartialEq)]
+pub enum Service {
+    RoadAccess,
+    OutsideConnection
+}
+
+impl Service {
+    //the service the overlay shows after this one, or None to turn it off
+    pub fn next(service: Option<Service>) -> Option<Service> {
+        match service {
+            None => Some(RoadAccess),
+            Some(RoadAccess) => Some(OutsideConnection),
+            Some(OutsideConnection) => None
+        }
+    }
+}
+
+impl fmt::Show for Service {
+    fn fmt(&self, buf: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RoadAccess => write!(buf, "Road access"),
+            OutsideConnection => write!(buf, "Connection to the map edge")
+        }
+    }
+}
+
+//the per-tile values that can be written by City::debug_dump
+pub enum DebugField {
+    RegionField,
+    TrafficField,
+    CongestionField,
+    PopulationField,
+    ResourcesField
+}
+
+impl DebugField {
+    fn value(&self, tile: &tile::Tile, resources: uint) -> f64 {
+        match *self {
+            RegionField => tile.regions[0] as f64,
+            TrafficField => tile.traffic as f64,
+            CongestionField => tile.congestion() as f64,
+            PopulationField => tile.population(),
+            ResourcesField => resources as f64
+        }
+    }
+}
+
+pub struct ActiveEvent {
+    pub kind: RandomEventKind,
+    pub days_left: uint
+}
+
+pub enum EventKind {
+    ZoneUpgraded(uint),
+    PeopleMovedIn(f64),
+    PeopleMovedOut(f64),
+    FundsLow(f64),
+    PaidOut(f64, f64),
+    RandomEventStarted(RandomEventKind),
+    RandomEventEnded(RandomEventKind),
+    BuildingBurned(uint, uint)
+}
+
+pub struct CityEvent {
+    pub day: uint,
+    pub kind: EventKind
+}
+
+impl fmt::Show for CityEvent {
+    fn fmt(&self, buf: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            ZoneUpgraded(level) => write!(buf, "Day {}: Zone upgraded to level {}", self.day, level),
+            PeopleMovedIn(count) => write!(buf, "Day {}: {:.0} people moved in", self.day, count),
+            PeopleMovedOut(count) => write!(buf, "Day {}: {:.0} people moved out", self.day, count),
+            FundsLow(funds) => write!(buf, "Day {}: Funds low (${:.0})", self.day, funds),
+            PaidOut(net, expenses) => write!(buf, "Day {}: Paid out ${:.0}, after ${:.0} in expenses", self.day, net, expenses),
+            RandomEventStarted(ref kind) => write!(buf, "Day {}: {} started", self.day, kind),
+            RandomEventEnded(ref kind) => write!(buf, "Day {}: {} ended", self.day, kind),
+            BuildingBurned(x, y) => write!(buf, "Day {}: A building burned down at ({}, {})", self.day, x, y)
+        }
+    }
+}
+
+pub struct BalanceReport {
+    pub residential: uint,
+    pub commercial: uint,
+    pub industrial: uint,
+    pub jobs: f64,
+    pub workers: f64
+}
+
+impl BalanceReport {
+    pub fn warning(&self) -> Option<String> {
+        if self.industrial > 0 && self.commercial == 0 {
+            Some("Industry has no shops to sell to".to_string())
+        } else if self.commercial > 0 && self.industrial == 0 {
+            Some("Shops have no industry to buy from".to_string())
+        } else if self.jobs > 50.0 && self.jobs > self.workers * 2.0 {
+            Some("Far more jobs than workers".to_string())
+        } else if self.workers > 50.0 && self.workers > self.jobs * 2.0 {
+            Some("Far more workers than jobs".to_string())
+        } else {
+            None
+        }
+    }
+}
+
+//how the price of building grows with the number of developed tiles
+#[deriving(Clone)]
+pub enum LandCost {
+    FlatLandCost,
+    //the price rises by this fraction for every developed tile
+    LinearLandCost(f64),
+    //the price rises by the fraction for every full step of this many developed tiles
+    SteppedLandCost(uint, f64)
+}
+
+pub struct CityParams {
+    pub birth_rate: f64,
+    pub death_rate: f64,
+    pub prop_can_work: f64,
+    pub disaster_chance: f64,
+    pub boon_chance: f64,
+    pub days_per_month: uint,
+    pub land_cost: LandCost
+}
+
+impl CityParams {
+    pub fn easy() -> CityParams {
+        CityParams {
+            birth_rate: 0.00065,
+            death_rate: 0.0002,
+            prop_can_work: 0.55,
+            disaster_chance: 0.002,
+            boon_chance: 0.01,
+            days_per_month: 30,
+            land_cost: FlatLandCost
+        }
+    }
+
+    pub fn normal() -> CityParams {
+        CityParams {
+            birth_rate: 0.00055,
+            death_rate: 0.00023,
+            prop_can_work: 0.5,
+            disaster_chance: 0.005,
+            boon_chance: 0.005,
+            days_per_month: 30,
+            land_cost: SteppedLandCost(100, 0.1)
+        }
+    }
+
+    pub fn hard() -> CityParams {
+        CityParams {
+            birth_rate: 0.00045,
+            death_rate: 0.00028,
+            prop_can_work: 0.45,
+            disaster_chance: 0.01,
+            boon_chance: 0.003,
+            days_per_month: 30,
+            land_cost: LinearLandCost(0.002)
+        }
+    }
+}
+
+//the part of a save that comes before the map
+struct Economy {
+    day: uint,
+    funds: f64,
+    earnings: f64,
+    population: f64,
+    employable: f64,
+    population_pool: f64,
+    employment_pool: f64,
+    residential_tax: f64,
+    commercial_tax: f64,
+    industrial_tax: f64
+}
+
+//everything in a save file, read in full before any of it replaces the current city
+struct SavedCity {
+    economy: Economy,
+    map: map::Map,
+    bookmarks: Vec<Option<Vector2f>>,
+    goods_price: f64
+}
+
 pub struct City {
     current_time: f32,
     time_per_day: f32,
@@ -10,10 +279,30 @@ pub struct City {
     population_pool: f64,
     employment_pool: f64,
     prop_can_work: f64,
+    disaster_chance: f64,
+    boon_chance: f64,
+
+    //the payout happens when days_since_payout reaches days_per_month
+    days_per_month: uint,
+    days_since_payout: uint,
+
+    land_cost: LandCost,
 
     birth_rate: f64,
     death_rate: f64,
 
+    //what was left over or missing during the last day, for the zone demand
+    empty_homes: f64,
+    free_jobs: f64,
+    unsold_goods: f64,
+    missing_goods: f64,
+
+    rng: XorShiftRng,
+    events: Vec<CityEvent>,
+    //the industrial and commercial tile indices of each delivery since the last drain
+    deliveries: Vec<(uint, uint)>,
+    pub active_events: Vec<ActiveEvent>,
+
     pub map: map::Map,
 
     pub population: f64,
@@ -22,297 +311,1746 @@ pub struct City {
     pub residential_tax: f64,
     pub commercial_tax: f64,
     pub industrial_tax: f64,
+    pub auto_balance_taxes: bool,
+    //the service whose gaps are tinted, if any
+    pub coverage_overlay: Option<Service>,
 
     pub earnings: f64,
+    //the part of this month's earnings that went to imports and upkeep
+    pub expenses: f64,
     pub funds: f64,
 
+    pub import_price: f64,
+    pub goods_price: f64,
+
+    pub peak_population: f64,
+    pub peak_funds: f64,
+
+    pub bookmarks: Vec<Option<Vector2f>>,
+
+    //grass far from development slowly turns into this tile, if there is one
+    pub regrowth_tile: Option<tile::Tile>,
+
     pub day: uint
 }
 
 impl City {
     pub fn new(map: map::Map) -> City {
+        City::with_params(map, CityParams::normal())
+    }
+
+    pub fn with_params(map: map::Map, params: CityParams) -> City {
         City {
             current_time: 0.0,
             time_per_day: 1.0,
 
             population_pool: 0.0,
             employment_pool: 0.0,
-            prop_can_work: 0.5,
+            prop_can_work: params.prop_can_work,
+            disaster_chance: params.disaster_chance,
+            boon_chance: params.boon_chance,
+
+            days_per_month: max(params.days_per_month, 1),
+            land_cost: params.land_cost.clone(),
+            days_since_payout: 0,
             
-            birth_rate: 0.00055,
-            death_rate: 0.00023,
+            birth_rate: params.birth_rate,
+            death_rate: params.death_rate,
+
+            empty_homes: 0.0,
+            free_jobs: 0.0,
+            unsold_goods: 0.0,
+            missing_goods: 0.0,
+
+            rng: task_rng().gen(),
+            events: Vec::new(),
+            deliveries: Vec::new(),
+            active_events: Vec::new(),
 
             map: map,
 
             population: 0.0,
             employable: 0.0,
 
-            residential_tax: 0.05,
-            commercial_tax: 0.05,
-            industrial_tax: 0.05,
+            residential_tax: DEFAULT_TAX,
+            commercial_tax: DEFAULT_TAX,
+            industrial_tax: DEFAULT_TAX,
+            auto_balance_taxes: false,
+            coverage_overlay: None,
 
             earnings: 0.0,
+            expenses: 0.0,
             funds: 0.0,
 
+            import_price: 20.0,
+            goods_price: BASE_GOODS_PRICE,
+
+            peak_population: 0.0,
+            peak_funds: 0.0,
+
+            bookmarks: Vec::from_elem(BOOKMARK_SLOTS, None),
+
+            regrowth_tile: None,
+
             day: 0
         }
     }
 
     pub fn bulldoze(&mut self, new_tile: &tile::Tile) {
-        for (mut tile, _) in self.map.selected() {
+        for (_, tile, _) in self.map.selected() {
             match tile.tile_type {
                 tile::Residential {population, ..} => self.population_pool += population,
                 tile::Commercial {population, ..} | tile::Industrial {population, ..} => self.employment_pool += population,
                 _ => {}
             }
 
-            *tile = new_tile.clone()
+            //the old labels tell tile_changed which region the tile was taken from
+            let regions = tile.regions.clone();
+            *tile = new_tile.clone();
+            tile.regions = regions;
         }
-    }
 
-    pub fn tiles_changed(&mut self) {
-        self.map.update_direction(tile::Road);
-        self.map.find_connected_regions(
-            |tile| match tile {
-                &tile::Road | &tile::Residential {..} | &tile::Commercial {..} | &tile::Industrial {..} => true,
-                _ => false
-            },
-            0
-        );
+        if new_tile.tile_type.footprint() != (1, 1) {
+            self.map.link_selected_footprint();
+        }
     }
 
-    pub fn update(&mut self, dt: f32) {
-        let mut pop_total = 0.0;
-        let mut commercial_revenue = 0.0;
-        let mut industrial_revenue = 0.0;
+    pub fn reset(&mut self) {
+        self.current_time = 0.0;
 
-        let mut empty_homes = 0.0;
-        let mut free_jobs = 0.0;
+        self.population_pool = 0.0;
+        self.employment_pool = 0.0;
+        self.events.clear();
+        self.deliveries.clear();
+        self.active_events.clear();
 
-        let mut stores = 0u;
-        let mut industries = 0u;
+        self.population = 0.0;
+        self.employable = 0.0;
 
-        self.current_time += dt;
-        if self.current_time < self.time_per_day {
-            return;
-        }
+        self.empty_homes = 0.0;
+        self.free_jobs = 0.0;
+        self.unsold_goods = 0.0;
+        self.missing_goods = 0.0;
 
-        self.day += 1;
-        self.current_time = 0.0;
+        self.reset_taxes();
 
-        if self.day % 30 == 0 {
-            self.funds += self.earnings;
-            self.earnings = 0.0;
-        }
+        self.earnings = 0.0;
+        self.expenses = 0.0;
+        self.funds = 0.0;
 
-        
-        let shuffled_indices = {
-            let mut shuffled_tiles = self.map.shuffled();
+        self.goods_price = BASE_GOODS_PRICE;
 
-            //population and employment distribution pass
-            for &(ref mut tile, ref mut resources, _) in shuffled_tiles {
-                match &mut tile.tile_type {
-                    &tile::Residential {ref mut population, max_pop_per_level, ..} => {
-                        let max_pop = (max_pop_per_level * (tile.variant + 1)) as f64;
+        self.peak_population = 0.0;
+        self.peak_funds = 0.0;
 
-                        let (pool, new_population) = distribute_pool(
-                            self.population_pool,
-                            *population,
-                            max_pop,
-                            self.birth_rate - self.death_rate
-                        );
+        self.bookmarks = Vec::from_elem(BOOKMARK_SLOTS, None);
 
-                        empty_homes += max_pop - new_population;
+        self.day = 0;
+        self.days_since_payout = 0;
+    }
 
-                        self.population_pool = pool;
-                        *population = new_population;
-                        pop_total += *population;
-                    },
-                    &tile::Commercial {ref mut population, max_pop_per_level, ..} => {
-                        let max_pop = (max_pop_per_level * (tile.variant + 1)) as f64;
+    pub fn save(&self, path: &Path) -> io::IoResult<()> {
+        let mut file = io::BufferedWriter::new(try!(io::File::create(path)));
 
-                        if (1.0 - self.commercial_tax) * 0.15 > task_rng().gen() {
-                            let (pool, new_population) = distribute_pool(
-                                self.employment_pool,
-                                *population,
-                                max_pop,
-                                0.0
-                            );
+        try!(self.write_economy(&mut file));
+        try!(self.map.write_to(&mut file));
+        try!(self.write_bookmarks(&mut file));
+        try!(self.write_market(&mut file));
 
-                            self.employment_pool = pool;
-                            *population = new_population;
-                        }
+        file.flush()
+    }
 
-                        stores += 1;
-                        free_jobs += max_pop - *population;
-                    },
-                    &tile::Industrial {ref mut production, ref mut population, max_pop_per_level, ..} => {
-                        if *resources > 0 && *population * 0.01 > task_rng().gen() {
-                            *production += 1;
-                            *resources -= 1;
-                        }
+    //the city is only replaced if the whole file could be read
+    pub fn load(&mut self, path: &Path, tile_atlas: &HashMap<&'static str, tile::Tile>) -> io::IoResult<()> {
+        let saved = try!(self.read_save(path, tile_atlas));
+        self.apply_save(saved);
+        Ok(())
+    }
 
-                        let max_pop = (max_pop_per_level * (tile.variant + 1)) as f64;
+    fn read_save(&self, path: &Path, tile_atlas: &HashMap<&'static str, tile::Tile>) -> io::IoResult<SavedCity> {
+        let mut file = io::BufferedReader::new(try!(io::File::open(path)));
 
-                        if (1.0 - self.industrial_tax) * 0.15 > task_rng().gen() {
-                            let (pool, new_population) = distribute_pool(
-                                self.employment_pool,
-                                *population,
-                                max_pop,
-                                0.0
-                            );
+        let economy = try!(read_economy(&mut file));
+        let map = try!(self.map.loaded_from(&mut file, tile_atlas));
+        let bookmarks = try!(read_bookmarks(&mut file));
+        let goods_price = try!(read_market(&mut file));
 
-                            self.employment_pool = pool;
-                            *population = new_population;
-                        }
+        Ok(SavedCity {
+            economy: economy,
+            map: map,
+            bookmarks: bookmarks,
+            goods_price: goods_price
+        })
+    }
 
-                        industries += 1;
-                        free_jobs += max_pop - *population;
-                    },
-                    _ => {}
-                }
+    fn apply_save(&mut self, saved: SavedCity) {
+        let SavedCity {economy, map, bookmarks, goods_price} = saved;
 
-                tile.update();
-            }
+        self.day = economy.day;
+        self.funds = economy.funds;
+        self.earnings = economy.earnings;
+        //only the net earnings are saved
+        self.expenses = 0.0;
+        self.population = economy.population;
+        self.employable = economy.employable;
+        self.population_pool = economy.population_pool;
+        self.employment_pool = economy.employment_pool;
+        self.residential_tax = economy.residential_tax;
+        self.commercial_tax = economy.commercial_tax;
+        self.industrial_tax = economy.industrial_tax;
 
-            shuffled_tiles.into_indices()
-        };
+        //the saves don't know when the last payout was, so it's assumed to follow the current period
+        self.days_since_payout = self.day % self.days_per_month;
 
-        //manufacture pass
-        for &index in shuffled_indices.iter() {
-            let (region, level) = match self.map.tile(index) {
-                &(tile::Tile {tile_type: tile::Industrial {..}, ref regions, variant, ..}, _, _) => {
-                    (regions[0], variant as u32 + 1)
-                },
-                _ => continue
-            };
+        self.map = map;
+        self.bookmarks = bookmarks;
+        self.goods_price = goods_price;
 
-            let mut received_resources = 0;
-            
-            for &(ref mut tile2, _, _) in self.map.tiles() {
-                if tile2.regions[0] == region {
-                    match tile2.tile_type {
-                        tile::Industrial {ref mut production, ..} => {
-                            if *production > 0 {
-                                received_resources += 1;
-                                *production -= 1;
-                            }
-
-                            if received_resources >= level {
-                                break;
-                            }
-                        },
-                        _ => {}
-                    }
-                }
-            }
+        self.tiles_changed();
+    }
 
-            let &(ref mut tile, _, _) = self.map.mut_tile(index);
-            match tile.tile_type {
-                tile::Industrial {ref mut stored_goods, production, ..} => *stored_goods += (received_resources + production) * level,
-                _ => unreachable!()
-            }
+    //writes one field of every tile as a plain PGM image, scaled so the highest value is white
+    pub fn debug_dump(&self, path: &Path, field: DebugField) -> io::IoResult<()> {
+        let mut file = io::BufferedWriter::new(try!(io::File::create(path)));
+        let (width, height) = self.map.size();
+
+        let values: Vec<f64> = range(0, width * height).map(|index| {
+            let &(ref tile, resources, _) = self.map.tile(index);
+            field.value(tile, resources)
+        }).collect();
+        let max_value = values.iter().fold(0.0f64, |max, &value| max.max(value));
+
+        try!(write!(file, "P2\n{} {}\n255\n", width, height));
+        for row in values.as_slice().chunks(width) {
+            let line: Vec<String> = row.iter().map(|&value| {
+                let shade = if max_value > 0.0 { value / max_value * 255.0 } else { 0.0 };
+                (shade.max(0.0) as uint).to_string()
+            }).collect();
+            try!(file.write_line(line.connect(" ").as_slice()));
         }
 
-        //goods distribution pass
-        for &index in shuffled_indices.iter() {
-            let (region, level, population) = {
-                let &(ref tile, _, _) = self.map.tile(index);
-                let population = match tile.tile_type {
-                    tile::Commercial {population, ..} => population,
-                    _ => continue
-                };
-                (tile.regions[0], tile.variant as u32 + 1, population)
-            };
+        file.flush()
+    }
 
-            let mut received_goods = 0;
-            let mut max_customers = 0.0;
+    //like save, but only the tiles that changed since the full save at baseline_path are written
+    pub fn save_delta(&self, path: &Path, baseline_path: &Path, baseline: &map::Map) -> io::IoResult<()> {
+        let mut file = io::BufferedWriter::new(try!(io::File::create(path)));
 
-            for &(ref mut tile2, _, _) in self.map.tiles() {
-                if tile2.regions[0] == region {
-                    match tile2.tile_type {
-                        tile::Industrial {ref mut stored_goods, ..} => {
-                            while *stored_goods > 0 && received_goods < level {
-                                *stored_goods -= 1;
-                                received_goods += 1;
-                                industrial_revenue += 100.0 * (1.0 - self.industrial_tax);
-                            }
-                        },
-                        tile::Residential {population, ..} => {
-                            max_customers += population;
-                        }
-                        _ => {}
-                    }
+        let baseline_name = baseline_path.as_vec();
+        try!(file.write_be_u32(baseline_name.len() as u32));
+        try!(file.write(baseline_name));
 
-                    if received_goods >= level {
-                        break;
-                    }
-                }
-            }
+        try!(self.write_economy(&mut file));
+        try!(self.map.write_delta_to(&mut file, baseline));
+        try!(self.write_bookmarks(&mut file));
+        try!(self.write_market(&mut file));
 
-            let production = (received_goods as f64 * 100.0 + 20.0 * task_rng().gen()) * (1.0 - self.commercial_tax);
-            commercial_revenue += production * max_customers * population / 100.0;
-        }
+        file.flush()
+    }
 
-        self.population_pool += self.population_pool * (self.birth_rate - self.death_rate);
+    pub fn load_delta(&mut self, path: &Path, tile_atlas: &HashMap<&'static str, tile::Tile>) -> io::IoResult<()> {
+        let mut file = io::BufferedReader::new(try!(io::File::open(path)));
 
-        let imigrants = 1.0 + (empty_homes - self.population_pool).max(0.0) * (free_jobs - self.employment_pool).max(0.0) * (1.0 - self.residential_tax) * 0.0001;
-        let prob = (empty_homes - self.population_pool).max(0.0) * (free_jobs - self.employment_pool).max(0.0) * (1.0 - self.residential_tax) * 0.00001;
-        
-        //people moving to the city
-        if stores > 0 && industries > 0 && prob > task_rng().gen() {
-            self.population_pool += imigrants;
-        }
+        let name_length = try!(file.read_be_u32()) as uint;
+        let baseline_path = Path::new(try!(file.read_exact(name_length)));
+        let baseline = try!(self.read_save(&baseline_path, tile_atlas));
 
-        //people moving from the city
-        if (self.population_pool > empty_homes || self.employment_pool > free_jobs) && (self.population_pool + self.employment_pool) * 0.01 > task_rng().gen() {
-            self.population_pool -= (self.population_pool + self.employment_pool) * 0.05 + 1.0;
-        }
+        let economy = try!(read_economy(&mut file));
+        let map = try!(baseline.map.delta_applied_from(&mut file, tile_atlas));
+        let bookmarks = try!(read_bookmarks(&mut file));
+        let goods_price = try!(read_market(&mut file));
 
-        pop_total += self.population_pool;
+        self.apply_save(SavedCity {
+            economy: economy,
+            map: map,
+            bookmarks: bookmarks,
+            goods_price: goods_price
+        });
 
-        let new_workers = (pop_total - self.population).abs() * self.prop_can_work;
-        self.employment_pool += new_workers;
-        self.employable += new_workers;
+        Ok(())
+    }
 
-        if self.employment_pool < 0.0 {
-            self.employment_pool = 0.0;
-        }
-        
-        if self.employable < 0.0 {
-            self.employable = 0.0;
+    fn write_bookmarks<W: Writer>(&self, file: &mut W) -> io::IoResult<()> {
+        try!(file.write_u8(self.bookmarks.len() as u8));
+
+        for bookmark in self.bookmarks.iter() {
+            match *bookmark {
+                Some(center) => {
+                    try!(file.write_u8(1));
+                    try!(file.write_be_f32(center.x));
+                    try!(file.write_be_f32(center.y));
+                },
+                None => try!(file.write_u8(0))
+            }
         }
 
-        self.population = pop_total;
+        Ok(())
+    }
 
-        self.earnings += (self.population - self.population_pool) * 15.0 * self.residential_tax;
-        self.earnings += commercial_revenue * self.commercial_tax;
-        self.earnings += industrial_revenue * self.industrial_tax;
+    fn write_market<W: Writer>(&self, file: &mut W) -> io::IoResult<()> {
+        file.write_be_f64(self.goods_price)
     }
 
-    pub fn get_homeless(&self) -> f64  {
-        self.population_pool
+    fn write_economy<W: Writer>(&self, file: &mut W) -> io::IoResult<()> {
+        try!(file.write_be_u32(self.day as u32));
+        try!(file.write_be_f64(self.funds));
+        try!(file.write_be_f64(self.earnings));
+        try!(file.write_be_f64(self.population));
+        try!(file.write_be_f64(self.employable));
+        try!(file.write_be_f64(self.population_pool));
+        try!(file.write_be_f64(self.employment_pool));
+        try!(file.write_be_f64(self.residential_tax));
+        try!(file.write_be_f64(self.commercial_tax));
+        file.write_be_f64(self.industrial_tax)
     }
 
-    pub fn get_unemployed(&self) -> f64  {
-        self.employment_pool
+    fn update_goods_price(&mut self) {
+        let change = (self.rng.gen::<f64>() * 2.0 - 1.0) * GOODS_PRICE_VOLATILITY;
+        let price = self.goods_price * (1.0 + change) + (BASE_GOODS_PRICE - self.goods_price) * GOODS_PRICE_REVERSION;
+        self.goods_price = price.max(MIN_GOODS_PRICE).min(MAX_GOODS_PRICE);
     }
-}
 
-fn distribute_pool(pool: f64, population: f64, max_pop: f64, change_rate: f64) -> (f64, f64) {
+    //what a store earns on each good, which shrinks when the goods are expensive
+    fn commercial_margin(&self) -> f64 {
+        2.0 * BASE_GOODS_PRICE - self.goods_price
+    }
 
-    let (pool, population) = if pool > 0.0 {
-        let moving = (max_pop - population).min(4.0).min(pool);
-        (pool - moving, population + moving)
-    } else {
-        (pool, population)
-    };
+    pub fn balance_report(&self) -> BalanceReport {
+        let counts = self.map.count_tiles();
 
-    let population = population + population * change_rate;
+        BalanceReport {
+            residential: counts.total_residential(),
+            commercial: counts.commercial,
+            industrial: counts.industrial,
+            jobs: counts.jobs as f64,
+            workers: self.employable
+        }
+    }
 
-    if population > max_pop {
+    pub fn reset_taxes(&mut self) {
+        self.residential_tax = DEFAULT_TAX;
+        self.commercial_tax = DEFAULT_TAX;
+        self.industrial_tax = DEFAULT_TAX;
+    }
+
+    //raises the taxes a little after a losing month and lowers them when the city is doing well
+    fn balance_taxes(&mut self, monthly_net: f64) {
+        let step = if monthly_net < 0.0 {
+            AUTO_TAX_STEP
+        } else if self.funds > FLUSH_FUNDS {
+            -AUTO_TAX_STEP
+        } else {
+            return;
+        };
+
+        self.residential_tax = (self.residential_tax + step).max(0.0).min(AUTO_TAX_MAX);
+        self.commercial_tax = (self.commercial_tax + step).max(0.0).min(AUTO_TAX_MAX);
+        self.industrial_tax = (self.industrial_tax + step).max(0.0).min(AUTO_TAX_MAX);
+    }
+
+    //residential, commercial and industrial demand, each between -1 and 1, where positive means more is needed
+    pub fn demand(&self) -> (f64, f64, f64) {
+        let jobs = demand_balance(self.employment_pool, self.free_jobs);
+
+        (
+            demand_balance(self.population_pool, self.empty_homes),
+            (jobs + demand_balance(self.unsold_goods, self.missing_goods)) / 2.0,
+            (jobs + demand_balance(self.missing_goods, self.unsold_goods)) / 2.0
+        )
+    }
+
+    pub fn days_per_month(&self) -> uint {
+        self.days_per_month
+    }
+
+    //takes effect from the next day, counting the days that already passed since the last payout
+    pub fn set_days_per_month(&mut self, days: uint) {
+        self.days_per_month = max(days, 1);
+    }
+
+    pub fn prop_can_work(&self) -> f64 {
+        self.prop_can_work
+    }
+
+    pub fn set_prop_can_work(&mut self, proportion: f64) {
+        self.prop_can_work = proportion.max(0.0).min(1.0);
+    }
+
+    //the share of residents that can work in each road region, which shrinks as the homes grow old
+    pub fn workforce_proportions(&self) -> Vec<f64> {
+        let (width, height) = self.map.size();
+        let mut residents = Vec::new();
+        let mut resident_age = Vec::new();
+
+        for index in range(0, width * height) {
+            let &(ref tile, _, _) = self.map.tile(index);
+            match tile.tile_type {
+                tile::Residential {population, ..} => {
+                    let region = tile.regions[0];
+                    while residents.len() <= region {
+                        residents.push(0.0);
+                        resident_age.push(0.0);
+                    }
+
+                    *residents.get_mut(region) += population;
+                    *resident_age.get_mut(region) += population * tile.age_days as f64;
+                },
+                _ => {}
+            }
+        }
+
+        residents.iter().zip(resident_age.iter()).map(|(&population, &age)| {
+            let average_age = if population > 0.0 { age / population } else { 0.0 };
+            let aging = (average_age / DECAY_AGE as f64).min(1.0);
+            self.prop_can_work * (1.0 - AGING_WORKFORCE_LOSS * aging)
+        }).collect()
+    }
+
+    //the share of the whole city that can work, weighted by where people live
+    pub fn effective_prop_can_work(&self) -> f64 {
+        let (width, height) = self.map.size();
+        let proportions = self.workforce_proportions();
+        let mut residents = 0.0;
+        let mut workers = 0.0;
+
+        for index in range(0, width * height) {
+            let &(ref tile, _, _) = self.map.tile(index);
+            match tile.tile_type {
+                tile::Residential {population, ..} => {
+                    residents += population;
+                    workers += population * proportions[tile.regions[0]];
+                },
+                _ => {}
+            }
+        }
+
+        if residents > 0.0 {
+            workers / residents
+        } else {
+            self.prop_can_work
+        }
+    }
+
+    pub fn days_until_payout(&self) -> uint {
+        max(self.days_per_month, self.days_since_payout + 1) - self.days_since_payout
+    }
+
+    //the earnings so far this month, extrapolated to the end of it
+    pub fn projected_monthly_net(&self) -> f64 {
+        let days_passed = self.days_since_payout;
+        if days_passed == 0 {
+            self.earnings
+        } else {
+            self.earnings / days_passed as f64 * self.days_per_month as f64
+        }
+    }
+
+    pub fn set_speed(&mut self, speed: f32) {
+        self.time_per_day = 1.0 / speed;
+    }
+
+    pub fn speed(&self) -> f32 {
+        1.0 / self.time_per_day
+    }
+
+    pub fn drain_events(&mut self) -> Vec<CityEvent> {
+        replace(&mut self.events, Vec::new())
+    }
+
+    pub fn drain_deliveries(&mut self) -> Vec<(uint, uint)> {
+        replace(&mut self.deliveries, Vec::new())
+    }
+
+    //people who move in from outside and wait for free homes
+    pub fn add_people(&mut self, amount: f64) {
+        self.population_pool += amount;
+    }
+
+    pub fn seed_rng(&mut self, seed: [u32, ..4]) {
+        self.rng.reseed(seed);
+    }
+
+    //how many times the base price it costs to build, now that the city has grown
+    pub fn land_cost_multiplier(&self) -> f64 {
+        let developed = self.map.count_tiles().developed();
+
+        match self.land_cost {
+            FlatLandCost => 1.0,
+            LinearLandCost(rate) => 1.0 + developed as f64 * rate,
+            SteppedLandCost(step, rate) => 1.0 + (developed / max(step, 1)) as f64 * rate
+        }
+    }
+
+    pub fn build_cost(&self, tile: &tile::Tile) -> f64 {
+        if tile.tile_type.follows_land_cost() {
+            tile.build_cost() as f64 * self.land_cost_multiplier()
+        } else {
+            tile.build_cost() as f64
+        }
+    }
+
+    //what it would cost to fill the rectangle with the tile, leaving the selection as it is
+    pub fn preview_cost(&self, start: Vector2i, end: Vector2i, new_tile: &tile::Tile) -> f64 {
+        self.map.preview_cost(start, end, self.build_cost(new_tile), |tile| !new_tile.tile_type.can_build_over(tile))
+    }
+
+    pub fn selection_cost(&mut self, new_tile: &tile::Tile) -> f64 {
+        let cost = self.build_cost(new_tile);
+        let mut total = 0.0;
+
+        for (_, tile, _) in self.map.selected() {
+            total += tile.replacement_cost(cost) - tile.demolish_refund();
+        }
+
+        total
+    }
+
+    pub fn tiles_changed(&mut self) {
+        self.map.update_direction(tile::Road);
+        self.map.find_connected_regions(|tile| connects_region(tile), 0);
+
+        if self.coverage_overlay.is_some() {
+            self.update_coverage_tints();
+        }
+    }
+
+    //cheaper than tiles_changed when only the tile at pos was replaced
+    pub fn tile_changed(&mut self, pos: &Vector2i) {
+        self.map.update_direction(tile::Road);
+        self.map.update_regions_incremental(pos, |tile| connects_region(tile), 0);
+
+        if self.coverage_overlay.is_some() {
+            self.update_coverage_tints();
+        }
+    }
+
+    //which regions have the service, indexed by region label
+    pub fn service_coverage(&self, service: Service) -> Vec<bool> {
+        match service {
+            RoadAccess => self.developable_regions(),
+            OutsideConnection => self.map.regions_on_edge(0)
+        }
+    }
+
+    //tints the buildings in regions without the service in coverage_overlay, or clears the tints when it's off
+    pub fn update_coverage_tints(&mut self) {
+        let coverage = match self.coverage_overlay {
+            Some(service) => Some(self.service_coverage(service)),
+            None => None
+        };
+
+        let (width, height) = self.map.size();
+        for index in range(0, width * height) {
+            let &(ref mut tile, _, _) = self.map.mut_tile(index);
+            let covered = match coverage {
+                Some(ref coverage) => match tile.tile_type {
+                    tile::Residential {..} | tile::Commercial {..} | tile::Industrial {..} | tile::Airport => {
+                        let region = tile.regions[0];
+                        region < coverage.len() && coverage[region]
+                    },
+                    _ => true
+                },
+                None => true
+            };
+
+            tile.tint = if covered { None } else { Some(Color::new_RGB(0xff, 0x60, 0x60)) };
+        }
+    }
+
+    //moves a single tile building onto grass, keeping its population, and reconnects both places
+    pub fn move_tile(&mut self, from: uint, to: uint) -> Result<(), String> {
+        try!(self.map.move_tile(from, to));
+
+        let (width, _) = self.map.size();
+        self.tile_changed(&Vector2i::new((from % width) as i32, (from / width) as i32));
+        self.tile_changed(&Vector2i::new((to % width) as i32, (to / width) as i32));
+        Ok(())
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        let mut pop_total = 0.0;
+        let mut commercial_revenue = 0.0;
+        let mut industrial_revenue = 0.0;
+
+        let mut empty_homes = 0.0;
+        let mut free_jobs = 0.0;
+
+        let mut stores = 0u;
+        let mut industries = 0u;
+
+        let mut missing_goods = 0.0;
+
+        self.current_time += dt;
+        if self.current_time < self.time_per_day {
+            return;
+        }
+
+        self.day += 1;
+        self.current_time = 0.0;
+
+        self.days_since_payout += 1;
+        if self.days_since_payout >= self.days_per_month {
+            self.days_since_payout = 0;
+
+            let net = self.earnings;
+            self.funds += self.earnings;
+            self.events.push(CityEvent { day: self.day, kind: PaidOut(net, self.expenses) });
+            self.earnings = 0.0;
+            self.expenses = 0.0;
+
+            if self.auto_balance_taxes {
+                self.balance_taxes(net);
+            }
+
+            if self.funds < 1000.0 {
+                self.events.push(CityEvent { day: self.day, kind: FundsLow(self.funds) });
+            }
+        }
+
+        self.update_random_events();
+        self.update_goods_price();
+        self.regrow_forest();
+
+        let connected_to_outside = self.map.regions_on_edge(0);
+
+        let (airports, airport_connected) = self.find_airports();
+        let upkeep = airports as f64 * AIRPORT_UPKEEP;
+        self.earnings -= upkeep;
+        self.expenses += upkeep;
+
+        let commute = self.commute_factors();
+        let waterfront = self.waterfront_factors();
+        let developable = self.developable_regions();
+
+        //residents, weighted by how much tax their homes bring in
+        let mut taxed_residents = 0.0;
+
+        let shuffled_indices = {
+            let mut shuffled_tiles = self.map.shuffled(&mut self.rng);
+
+            //population and employment distribution pass
+            for (index, &(ref mut tile, ref mut resources, _)) in shuffled_tiles {
+                let undeveloped = match tile.tile_type {
+                    tile::Residential {population, ..} |
+                    tile::Commercial {population, ..} |
+                    tile::Industrial {population, ..} => population == 0.0 && tile.variant == 0 && !developable[tile.regions[0]],
+                    _ => false
+                };
+                tile.undeveloped = undeveloped;
+
+                match &mut tile.tile_type {
+                    //nothing moves in before there's a road
+                    _ if undeveloped => {},
+                    &tile::Residential {ref mut population, max_pop_per_level, ..} => {
+                        let max_pop = (max_pop_per_level * (tile.variant + 1)) as f64;
+
+                        let (pool, new_population) = distribute_pool(
+                            self.population_pool,
+                            *population,
+                            max_pop,
+                            self.birth_rate * (1.0 + waterfront[index]) - self.death_rate
+                        );
+
+                        empty_homes += max_pop - new_population;
+
+                        self.population_pool = pool;
+                        *population = new_population;
+                        pop_total += *population;
+                        taxed_residents += *population * (1.0 + waterfront[index]);
+                    },
+                    &tile::Commercial {ref mut population, max_pop_per_level, ..} => {
+                        let max_pop = (max_pop_per_level * (tile.variant + 1)) as f64;
+
+                        if (1.0 - self.commercial_tax) * 0.15 * commute[index] > self.rng.gen() {
+                            let (pool, new_population) = distribute_pool(
+                                self.employment_pool,
+                                *population,
+                                max_pop,
+                                0.0
+                            );
+
+                            self.employment_pool = pool;
+                            *population = new_population;
+                        }
+
+                        stores += 1;
+                        free_jobs += max_pop - *population;
+                    },
+                    &tile::Industrial {ref mut production, ref mut population, max_pop_per_level, ..} => {
+                        if *resources > 0 && *population * 0.01 > self.rng.gen() {
+                            *production += 1;
+                            *resources -= 1;
+                        }
+
+                        let max_pop = (max_pop_per_level * (tile.variant + 1)) as f64;
+
+                        if (1.0 - self.industrial_tax) * 0.15 * commute[index] > self.rng.gen() {
+                            let (pool, new_population) = distribute_pool(
+                                self.employment_pool,
+                                *population,
+                                max_pop,
+                                0.0
+                            );
+
+                            self.employment_pool = pool;
+                            *population = new_population;
+                        }
+
+                        industries += 1;
+                        free_jobs += max_pop - *population;
+                    },
+                    _ => {}
+                }
+
+                if tile.update(&mut self.rng) {
+                    self.events.push(CityEvent { day: self.day, kind: ZoneUpgraded(tile.variant + 1) });
+                }
+
+                //old buildings that haven't grown in a long time slowly fall apart
+                if tile.age_days > DECAY_AGE && DECAY_CHANCE > self.rng.gen() {
+                    let displaced = tile.decay();
+                    match tile.tile_type {
+                        tile::Residential {..} => self.population_pool += displaced,
+                        _ => self.employment_pool += displaced
+                    }
+                }
+            }
+
+            shuffled_tiles.into_indices()
+        };
+
+        let congestion = self.update_traffic();
+
+        //manufacture pass
+        for &index in shuffled_indices.iter() {
+            let (region, level) = match self.map.tile(index) {
+                &(tile::Tile {tile_type: tile::Industrial {..}, ref regions, variant, ..}, _, _) => {
+                    (regions[0], variant as u32 + 1)
+                },
+                _ => continue
+            };
+
+            let mut received_resources = 0;
+            let members: Vec<uint> = self.map.region_tiles(0, region).map(|&member| member).collect();
+
+            for &member in members.iter() {
+                let &(ref mut tile2, _, _) = self.map.mut_tile(member);
+                match tile2.tile_type {
+                    tile::Industrial {ref mut production, ..} => {
+                        if *production > 0 {
+                            received_resources += 1;
+                            *production -= 1;
+                        }
+
+                        if received_resources >= level {
+                            break;
+                        }
+                    },
+                    _ => {}
+                }
+            }
+
+            let production = match self.map.tile(index) {
+                &(tile::Tile {tile_type: tile::Industrial {production, ..}, ..}, _, _) => production,
+                _ => unreachable!()
+            };
+
+            //regions with a road to the map edge can import what neither they nor the tile itself can supply
+            let shortfall = level - min(level, received_resources + production);
+            if shortfall > 0 && region < connected_to_outside.len() && connected_to_outside[region] {
+                let budget = self.funds + self.earnings;
+                let affordable = if self.import_price > 0.0 {
+                    (budget / self.import_price).max(0.0) as u32
+                } else {
+                    shortfall
+                };
+
+                let imported = min(shortfall, affordable);
+                let cost = imported as f64 * self.import_price;
+                received_resources += imported;
+                self.earnings -= cost;
+                self.expenses += cost;
+            }
+
+            let &(ref mut tile, _, _) = self.map.mut_tile(index);
+            match tile.tile_type {
+                tile::Industrial {ref mut stored_goods, production, ..} => {
+                    //what doesn't fit in the stock is wasted
+                    *stored_goods = (*stored_goods + (received_resources + production) * level).min(MAX_STORED_GOODS_PER_LEVEL * level);
+                },
+                _ => unreachable!()
+            }
+        }
+
+        //goods distribution pass
+        for &index in shuffled_indices.iter() {
+            let (region, level, population, goods_buffer) = {
+                let &(ref tile, _, _) = self.map.tile(index);
+                let (population, goods_buffer) = match tile.tile_type {
+                    tile::Commercial {population, goods_buffer, ..} => (population, goods_buffer),
+                    _ => continue
+                };
+                (tile.regions[0], tile.variant as u32 + 1, population, goods_buffer)
+            };
+
+            //stores restock what they sell in a day, and fill up their buffer when there's more to get
+            let wanted = level + (GOODS_BUFFER_PER_LEVEL * level).saturating_sub(goods_buffer);
+            let mut received_goods = 0;
+            let mut max_customers = 0.0;
+            let members: Vec<uint> = self.map.region_tiles(0, region).map(|&member| member).collect();
+
+            for &member in members.iter() {
+                let &(ref mut tile2, _, _) = self.map.mut_tile(member);
+                match tile2.tile_type {
+                    tile::Industrial {ref mut stored_goods, ..} => {
+                        let delivered_before = received_goods;
+                        while *stored_goods > 0 && received_goods < wanted {
+                            *stored_goods -= 1;
+                            received_goods += 1;
+                            industrial_revenue += self.goods_price * (1.0 - self.industrial_tax);
+                        }
+
+                        if received_goods > delivered_before {
+                            self.deliveries.push((member, index));
+                        }
+                    },
+                    tile::Residential {population, ..} => {
+                        max_customers += population;
+                    }
+                    _ => {}
+                }
+
+                if received_goods >= wanted {
+                    break;
+                }
+            }
+
+            let available = goods_buffer + received_goods;
+            let sold_goods = available.min(level);
+            missing_goods += (level - sold_goods) as f64;
+
+            let &(ref mut tile, _, _) = self.map.mut_tile(index);
+            match tile.tile_type {
+                tile::Commercial {received_goods: ref mut last_received_goods, ref mut goods_buffer, ref mut supplied, ..} => {
+                    *last_received_goods = sold_goods;
+                    *goods_buffer = available - sold_goods;
+                    *supplied = true;
+                },
+                _ => unreachable!()
+            }
+
+            let production = (sold_goods as f64 * self.commercial_margin() + 20.0 * self.rng.gen()) * (1.0 - self.commercial_tax);
+
+            //congested roads keep some customers away
+            let accessibility = if region < congestion.len() { 1.0 - congestion[region] as f64 * 0.2 } else { 1.0 };
+            commercial_revenue += production * max_customers * population * accessibility / 100.0;
+        }
+
+        self.unsold_goods = 0.0;
+        for &index in shuffled_indices.iter() {
+            match self.map.tile(index) {
+                &(tile::Tile {tile_type: tile::Industrial {stored_goods, ..}, ..}, _, _) => self.unsold_goods += stored_goods as f64,
+                _ => {}
+            }
+        }
+        self.missing_goods = missing_goods;
+        self.empty_homes = empty_homes;
+        self.free_jobs = free_jobs;
+
+        self.population_pool += self.population_pool * (self.birth_rate - self.death_rate);
+
+        let free_homes = (empty_homes - self.population_pool).max(0.0);
+        let max_imigrants = 1.0 + free_homes * 0.1;
+        let mut imigrants = (1.0 + free_homes * (free_jobs - self.employment_pool).max(0.0) * (1.0 - self.residential_tax) * 0.0001).min(max_imigrants);
+        let mut prob = (empty_homes - self.population_pool).max(0.0) * (free_jobs - self.employment_pool).max(0.0) * (1.0 - self.residential_tax) * 0.00001;
+
+        prob *= self.immigration_multiplier();
+
+        if airport_connected {
+            imigrants = (imigrants * AIRPORT_IMMIGRATION_BOOST).min(max_imigrants);
+            prob *= AIRPORT_IMMIGRATION_BOOST;
+        }
+        
+        //people moving to the city
+        if stores > 0 && industries > 0 && prob > self.rng.gen() {
+            self.population_pool += imigrants;
+            self.events.push(CityEvent { day: self.day, kind: PeopleMovedIn(imigrants) });
+        }
+
+        //people moving from the city
+        if (self.population_pool > empty_homes || self.employment_pool > free_jobs) && (self.population_pool + self.employment_pool) * 0.01 > self.rng.gen() {
+            let emigrants = (self.population_pool + self.employment_pool) * 0.05 + 1.0;
+            self.population_pool -= emigrants;
+            self.events.push(CityEvent { day: self.day, kind: PeopleMovedOut(emigrants) });
+        }
+
+        pop_total += self.population_pool;
+
+        let new_workers = (pop_total - self.population).abs() * self.effective_prop_can_work();
+        self.employment_pool += new_workers;
+        self.employable += new_workers;
+
+        if self.employment_pool < 0.0 {
+            self.employment_pool = 0.0;
+        }
+        
+        if self.employable < 0.0 {
+            self.employable = 0.0;
+        }
+
+        self.population = pop_total;
+
+        self.earnings += taxed_residents * 15.0 * self.residential_tax;
+        let revenue_multiplier = self.revenue_multiplier();
+        self.earnings += commercial_revenue * self.commercial_tax * revenue_multiplier;
+        self.earnings += industrial_revenue * self.industrial_tax * revenue_multiplier;
+
+        self.peak_population = self.peak_population.max(self.population);
+        self.peak_funds = self.peak_funds.max(self.funds);
+
+        if self.coverage_overlay.is_some() {
+            self.update_coverage_tints();
+        }
+    }
+
+    //replays the growth conditions from update for a single tile and lists the ones that hold it back
+    pub fn growth_limits(&self, pos: &Vector2i) -> Vec<String> {
+        let mut limits = Vec::new();
+
+        let (tile, resources) = match self.map.tile_at(pos) {
+            Some(&(ref tile, resources, _)) => (tile, resources),
+            None => return limits
+        };
+
+        let (population, max_pop_per_level, max_levels) = match tile.tile_type {
+            tile::Residential {population, max_pop_per_level, max_levels, ..} |
+            tile::Commercial {population, max_pop_per_level, max_levels, ..} |
+            tile::Industrial {population, max_pop_per_level, max_levels, ..} => (population, max_pop_per_level, max_levels),
+            _ => {
+                limits.push("Only zones can grow".to_string());
+                return limits;
+            }
+        };
+
+        let region = tile.regions[0];
+        match self.map.region_summary(0, region) {
+            Some(summary) => {
+                if summary.roads == 0 {
+                    limits.push("Not connected to any road".to_string());
+                }
+
+                match tile.tile_type {
+                    tile::Residential {..} if summary.jobs == 0 => limits.push("No jobs in this region".to_string()),
+                    tile::Commercial {..} | tile::Industrial {..} if summary.homes == 0 => limits.push("No homes in this region".to_string()),
+                    _ => {}
+                }
+
+                if !summary.connected_to_edge {
+                    limits.push("No road to the map edge".to_string());
+                }
+            },
+            None => limits.push("Not connected to any road".to_string())
+        }
+
+        let max_pop = (max_pop_per_level * (tile.variant + 1)) as f64;
+
+        match tile.tile_type {
+            tile::Residential {..} => {
+                if population < max_pop && self.population_pool <= 0.0 {
+                    limits.push("No one is looking for a home".to_string());
+                }
+            },
+            tile::Commercial {..} => {
+                if population < max_pop && self.employment_pool <= 0.0 {
+                    limits.push("No unemployed workers".to_string());
+                }
+
+                if tile.tile_type.lacks_goods() {
+                    limits.push("No goods delivered".to_string());
+                }
+            },
+            tile::Industrial {..} => {
+                if population < max_pop && self.employment_pool <= 0.0 {
+                    limits.push("No unemployed workers".to_string());
+                }
+
+                if resources == 0 {
+                    limits.push("No resources left".to_string());
+                }
+            },
+            _ => {}
+        }
+
+        let tax = match tile.tile_type {
+            tile::Residential {..} => self.residential_tax,
+            tile::Commercial {..} => self.commercial_tax,
+            _ => self.industrial_tax
+        };
+        if tax > 0.2 {
+            limits.push(format!("High taxes ({:.0}%)", tax * 100.0));
+        }
+
+        if tile.variant >= max_levels {
+            limits.push("Fully grown".to_string());
+        } else if population < max_pop {
+            limits.push(format!("Needs {:.0} more to level up", max_pop - population));
+        }
+
+        if tile.abandoned {
+            limits.push("Abandoned".to_string());
+        }
+
+        limits
+    }
+
+    pub fn is_event_active(&self, kind: &RandomEventKind) -> bool {
+        self.active_events.iter().any(|event| event.kind == *kind)
+    }
+
+    fn revenue_multiplier(&self) -> f64 {
+        let mut multiplier = 1.0;
+        if self.is_event_active(&EconomicBoom) {
+            multiplier *= 1.5;
+        }
+        if self.is_event_active(&Recession) {
+            multiplier *= 0.7;
+        }
+        multiplier
+    }
+
+    fn immigration_multiplier(&self) -> f64 {
+        let mut multiplier = 1.0;
+        if self.is_event_active(&PopulationSurge) {
+            multiplier *= 3.0;
+        }
+        if self.is_event_active(&Recession) {
+            multiplier *= 0.5;
+        }
+        multiplier
+    }
+
+    //counts down the active events and rolls for new ones, once per day
+    fn update_random_events(&mut self) {
+        for event in self.active_events.mut_iter() {
+            event.days_left -= 1;
+        }
+
+        let day = self.day;
+        let (ended, active) = replace(&mut self.active_events, Vec::new()).partition(|event| event.days_left == 0);
+        self.active_events = active;
+        for event in ended.move_iter() {
+            if event.kind != Fire {
+                self.events.push(CityEvent { day: day, kind: RandomEventEnded(event.kind) });
+            }
+        }
+
+        if self.disaster_chance > self.rng.gen() {
+            let kind = if self.rng.gen() { Fire } else { Recession };
+            self.start_event(kind);
+        }
+
+        if self.boon_chance > self.rng.gen() {
+            let kind = if self.rng.gen() { EconomicBoom } else { PopulationSurge };
+            self.start_event(kind);
+        }
+    }
+
+    fn start_event(&mut self, kind: RandomEventKind) {
+        if self.is_event_active(&kind) {
+            return;
+        }
+
+        match kind {
+            Fire => self.start_fire(),
+            _ => self.events.push(CityEvent { day: self.day, kind: RandomEventStarted(kind.clone()) })
+        }
+
+        self.active_events.push(ActiveEvent { days_left: kind.duration(), kind: kind });
+    }
+
+    //burns a random building down to an empty lot of the same zone
+    fn start_fire(&mut self) {
+        let (width, height) = self.map.size();
+        let buildings: Vec<uint> = range(0, width * height).filter(|&index| {
+            let &(ref tile, _, _) = self.map.tile(index);
+            match tile.tile_type {
+                tile::Residential {..} | tile::Commercial {..} | tile::Industrial {..} => tile.variant > 0 || tile.population() > 0.0,
+                _ => false
+            }
+        }).collect();
+
+        if buildings.is_empty() {
+            return;
+        }
+
+        let index = buildings[self.rng.gen_range(0, buildings.len())];
+        let &(ref mut tile, _, _) = self.map.mut_tile(index);
+        match tile.tile_type {
+            tile::Residential {population, ..} => self.population_pool += population,
+            tile::Commercial {population, ..} | tile::Industrial {population, ..} => self.employment_pool += population,
+            _ => {}
+        }
+        tile.set_population(0.0);
+        tile.variant = 0;
+
+        self.events.push(CityEvent { day: self.day, kind: BuildingBurned(index % width, index / width) });
+    }
+
+    fn regrow_forest(&mut self) {
+        let forest = match self.regrowth_tile {
+            Some(ref forest) => forest.clone(),
+            None => return
+        };
+
+        let (width, height) = self.map.size();
+
+        for index in range(0, width * height) {
+            match self.map.tile(index) {
+                &(tile::Tile {tile_type: tile::Grass, ..}, _, map::Deselected) => {},
+                _ => continue
+            }
+
+            if REGROWTH_CHANCE <= self.rng.gen() || self.near_development(index % width, index / width) {
+                continue;
+            }
+
+            let &(ref mut tile, _, _) = self.map.mut_tile(index);
+            let regions = tile.regions.clone();
+            *tile = forest.clone();
+            tile.regions = regions;
+        }
+    }
+
+    //the growth and tax bonus for a home at the position, from the closest water within reach
+    pub fn waterfront_bonus(&self, pos: &Vector2i) -> f64 {
+        let mut closest = None;
+
+        for dy in range(-WATERFRONT_DISTANCE, WATERFRONT_DISTANCE + 1) {
+            for dx in range(-WATERFRONT_DISTANCE, WATERFRONT_DISTANCE + 1) {
+                let neighbor = Vector2i::new(pos.x + dx as i32, pos.y + dy as i32);
+                match self.map.tile_at(&neighbor) {
+                    Some(&(ref tile, _, _)) if tile.tile_type.similar_to(&tile::Water) => {
+                        let distance = max(dx.abs(), dy.abs());
+                        if distance > 0 && closest.map_or(true, |closest| distance < closest) {
+                            closest = Some(distance);
+                        }
+                    },
+                    _ => {}
+                }
+            }
+        }
+
+        match closest {
+            Some(distance) => WATERFRONT_BONUS * 0.5f64.powi(distance as i32 - 1),
+            None => 0.0
+        }
+    }
+
+    //the waterfront bonus of each residential tile, and 0 for everything else
+    fn waterfront_factors(&self) -> Vec<f64> {
+        let (width, height) = self.map.size();
+
+        range(0, width * height).map(|index| {
+            let &(ref tile, _, _) = self.map.tile(index);
+            match tile.tile_type {
+                tile::Residential {..} => self.waterfront_bonus(&Vector2i::new((index % width) as i32, (index / width) as i32)),
+                _ => 0.0
+            }
+        }).collect()
+    }
+
+    fn near_development(&self, x: uint, y: uint) -> bool {
+        for dy in range(-REGROWTH_DISTANCE, REGROWTH_DISTANCE + 1) {
+            for dx in range(-REGROWTH_DISTANCE, REGROWTH_DISTANCE + 1) {
+                let pos = Vector2i::new(x as i32 + dx as i32, y as i32 + dy as i32);
+                match self.map.tile_at(&pos) {
+                    Some(&(ref tile, _, _)) => match tile.tile_type {
+                        tile::Road | tile::Residential {..} | tile::Commercial {..} | tile::Industrial {..} | tile::Airport => return true,
+                        _ => {}
+                    },
+                    None => {}
+                }
+            }
+        }
+
+        false
+    }
+
+    //zones can only start developing in regions with at least one road
+    fn developable_regions(&self) -> Vec<bool> {
+        let (width, height) = self.map.size();
+        let mut developable = Vec::new();
+
+        for index in range(0, width * height) {
+            let &(ref tile, _, _) = self.map.tile(index);
+            let region = tile.regions[0];
+            while developable.len() <= region {
+                developable.push(false);
+            }
+
+            match tile.tile_type {
+                tile::Road if region != 0 => *developable.get_mut(region) = true,
+                _ => {}
+            }
+        }
+
+        developable
+    }
+
+    //scales the hiring chance of each workplace by the distance to the closest home in its region,
+    //and is 1 for every other tile
+    fn commute_factors(&self) -> Vec<f64> {
+        let (width, height) = self.map.size();
+
+        let homes: Vec<(uint, uint)> = range(0, width * height).filter_map(|index| {
+            let &(ref tile, _, _) = self.map.tile(index);
+            match tile.tile_type {
+                tile::Residential {..} => Some((index, tile.regions[0])),
+                _ => None
+            }
+        }).collect();
+
+        range(0, width * height).map(|index| {
+            let &(ref tile, _, _) = self.map.tile(index);
+            match tile.tile_type {
+                tile::Commercial {..} | tile::Industrial {..} => {
+                    let (x, y) = ((index % width) as int, (index / width) as int);
+                    let region = tile.regions[0];
+
+                    //without a home in reach, the workers have to come from as far as it gets
+                    let distance = homes.iter()
+                        .filter(|&&(_, home_region)| home_region == region)
+                        .map(|&(home, _)| ((home % width) as int - x).abs() + ((home / width) as int - y).abs())
+                        .min()
+                        .unwrap_or((width + height) as int);
+
+                    1.0 / (1.0 + distance as f64 / COMMUTE_FALLOFF)
+                },
+                _ => 1.0
+            }
+        }).collect()
+    }
+
+    //returns the number of airports and if any of them can be reached by road
+    fn find_airports(&self) -> (uint, bool) {
+        let (width, height) = self.map.size();
+        let mut airports = 0;
+        let mut connected = false;
+
+        for index in range(0, width * height) {
+            let &(ref tile, _, _) = self.map.tile(index);
+            if tile.tile_type.similar_to(&tile::Airport) {
+                //every tile of the airport may be next to the road, but it's only counted once
+                if tile.anchor.is_none() {
+                    airports += 1;
+                }
+                connected = connected || self.map.is_adjacent_to(&Vector2i::new((index % width) as i32, (index / width) as i32), &tile::Road);
+            }
+        }
+
+        (airports, connected)
+    }
+
+    //spreads each region's commuters over its roads and returns the average congestion per region
+    fn update_traffic(&mut self) -> Vec<f32> {
+        let mut residents = Vec::new();
+        let mut employees = Vec::new();
+        let mut roads = Vec::new();
+
+        for &(ref tile, _, _) in self.map.tiles() {
+            let region = tile.regions[0];
+            if region >= roads.len() {
+                residents.grow(region + 1 - roads.len(), &0.0f64);
+                employees.grow(region + 1 - roads.len(), &0.0f64);
+                roads.grow(region + 1 - roads.len(), &0u);
+            }
+
+            match tile.tile_type {
+                tile::Residential {population, ..} => *residents.get_mut(region) += population,
+                tile::Commercial {population, ..} | tile::Industrial {population, ..} => *employees.get_mut(region) += population,
+                tile::Road => *roads.get_mut(region) += 1,
+                _ => {}
+            }
+        }
+
+        //only residents with a job in the same region commute over its roads
+        let traffic: Vec<f32> = range(0, roads.len()).map(|region| {
+            if region == 0 || roads[region] == 0 {
+                0.0
+            } else {
+                (residents[region].min(employees[region]) / roads[region] as f64) as f32
+            }
+        }).collect();
+
+        for &(ref mut tile, _, _) in self.map.tiles() {
+            tile.traffic = match tile.tile_type {
+                tile::Road => traffic[tile.regions[0]],
+                _ => 0.0
+            };
+        }
+
+        traffic.iter().map(|&t| (t / tile::ROAD_CAPACITY).min(1.0)).collect()
+    }
+
+    //people waiting for a home
+    pub fn homeless(&self) -> f64 {
+        self.population_pool
+    }
+
+    //workers waiting for a job
+    pub fn unemployed(&self) -> f64 {
+        self.employment_pool
+    }
+
+    //the homeless and the unemployed, as (population pool, employment pool)
+    pub fn pools(&self) -> (f64, f64) {
+        (self.population_pool, self.employment_pool)
+    }
+
+    //the people who have moved into a home
+    pub fn housed(&self) -> f64 {
+        self.population - self.population_pool
+    }
+
+    //what has been earned since the last payout
+    pub fn monthly_earnings(&self) -> f64 {
+        self.earnings
+    }
+
+    //how far the current day has come, between 0 and 1
+    pub fn time_into_day(&self) -> f32 {
+        (self.current_time / self.time_per_day).min(1.0)
+    }
+
+    pub fn time_per_day(&self) -> f32 {
+        self.time_per_day
+    }
+
+    //the residential, commercial and industrial tax rates
+    pub fn taxes(&self) -> (f64, f64, f64) {
+        (self.residential_tax, self.commercial_tax, self.industrial_tax)
+    }
+
+    //the daily change in population from births and deaths
+    pub fn growth_rate(&self) -> f64 {
+        self.birth_rate - self.death_rate
+    }
+
+    //the goods left unsold and the goods the stores went without during the last day
+    pub fn goods_balance(&self) -> (f64, f64) {
+        (self.unsold_goods, self.missing_goods)
+    }
+
+    //the homes and jobs that were left empty during the last day
+    pub fn vacancies(&self) -> (f64, f64) {
+        (self.empty_homes, self.free_jobs)
+    }
+
+    pub fn get_homeless(&self) -> f64  {
+        self.homeless()
+    }
+
+    pub fn get_unemployed(&self) -> f64  {
+        self.unemployed()
+    }
+}
+
+fn read_economy<R: Reader>(file: &mut R) -> io::IoResult<Economy> {
+    Ok(Economy {
+        day: try!(file.read_be_u32()) as uint,
+        funds: try!(file.read_be_f64()),
+        earnings: try!(file.read_be_f64()),
+        population: try!(file.read_be_f64()),
+        employable: try!(file.read_be_f64()),
+        population_pool: try!(file.read_be_f64()),
+        employment_pool: try!(file.read_be_f64()),
+        residential_tax: try!(file.read_be_f64()),
+        commercial_tax: try!(file.read_be_f64()),
+        industrial_tax: try!(file.read_be_f64())
+    })
+}
+
+//bookmarks were added to the end of the file, so older saves simply have none
+fn read_bookmarks<R: Reader>(file: &mut R) -> io::IoResult<Vec<Option<Vector2f>>> {
+    let mut bookmarks = Vec::from_elem(BOOKMARK_SLOTS, None);
+
+    let count = match file.read_u8() {
+        Ok(count) => count as uint,
+        Err(io::IoError {kind: io::EndOfFile, ..}) => return Ok(bookmarks),
+        Err(e) => return Err(e)
+    };
+
+    for slot in range(0, count) {
+        let bookmark = if try!(file.read_u8()) != 0 {
+            let x = try!(file.read_be_f32());
+            let y = try!(file.read_be_f32());
+            Some(Vector2f::new(x, y))
+        } else {
+            None
+        };
+
+        if slot < BOOKMARK_SLOTS {
+            *bookmarks.get_mut(slot) = bookmark;
+        }
+    }
+
+    Ok(bookmarks)
+}
+
+//the goods price comes after the bookmarks, so older saves start from the base price
+fn read_market<R: Reader>(file: &mut R) -> io::IoResult<f64> {
+    match file.read_be_f64() {
+        Ok(price) => Ok(price),
+        Err(io::IoError {kind: io::EndOfFile, ..}) => Ok(BASE_GOODS_PRICE),
+        Err(e) => Err(e)
+    }
+}
+
+fn distribute_pool(pool: f64, population: f64, max_pop: f64, change_rate: f64) -> (f64, f64) {
+
+    let (pool, population) = if pool > 0.0 {
+        let moving = (max_pop - population).min(4.0).min(pool);
+        (pool - moving, population + moving)
+    } else {
+        (pool, population)
+    };
+
+    let population = population + population * change_rate;
+
+    if population > max_pop {
         (pool + (population - max_pop), max_pop)
     } else {
         (pool, population)
     }
-}
\ No newline at end of file
+}
+
+//how much more of something is wanted than available, between -1 and 1
+fn demand_balance(wanted: f64, available: f64) -> f64 {
+    let total = wanted.max(0.0) + available.max(0.0);
+    if total > 0.0 {
+        (wanted.max(0.0) - available.max(0.0)) / total
+    } else {
+        0.0
+    }
+}
+
+//the tile types that make up the connected regions of type 0
+fn connects_region(tile_type: &tile::TileType) -> bool {
+    match *tile_type {
+        tile::Road | tile::Residential {..} | tile::Commercial {..} | tile::Industrial {..} => true,
+        _ => false
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::HashMap;
+
+    use map;
+    use tile;
+    use tile::Tile;
+    use super::{City, CityParams, RoadAccess, OutsideConnection, LinearLandCost};
+
+    static SEED: [u32, ..4] = [1, 2, 3, 4];
+
+    //normal rates, but no random events to disturb the numbers
+    fn quiet_params() -> CityParams {
+        let mut params = CityParams::normal();
+        params.disaster_chance = 0.0;
+        params.boon_chance = 0.0;
+        params
+    }
+
+    fn place(map: &mut map::Map, tile_atlas: &HashMap<&'static str, Tile>, x: uint, y: uint, name: &'static str) {
+        let (width, _) = map.size();
+        let &(ref mut tile, _, _) = map.mut_tile(y * width + x);
+        *tile = tile_atlas.find(&name).expect("unknown tile").clone();
+    }
+
+    //a flat 10x10 map with a road across the middle, homes above it and jobs below it
+    fn test_city(tile_atlas: &HashMap<&'static str, Tile>) -> City {
+        let mut params = map::GenerationParams::new();
+        params.width = 10;
+        params.height = 10;
+        params.flat = true;
+
+        let mut map = map::Map::empty(8);
+        map.regenerate(SEED, &params, tile_atlas);
+
+        for x in range(0u, 10) {
+            place(&mut map, tile_atlas, x, 5, "road");
+        }
+
+        for x in range(0u, 5) {
+            place(&mut map, tile_atlas, x, 4, "residential");
+        }
+
+        for x in range(0u, 3) {
+            place(&mut map, tile_atlas, x, 6, "commercial");
+        }
+
+        for x in range(5u, 10) {
+            place(&mut map, tile_atlas, x, 6, "industrial");
+        }
+
+        let mut city = City::with_params(map, quiet_params());
+        city.seed_rng(SEED);
+        city.tiles_changed();
+        city
+    }
+
+    fn run_days(city: &mut City, days: uint) {
+        for _ in range(0, days) {
+            city.update(1.0);
+        }
+    }
+
+    #[test]
+    fn every_update_is_one_day() {
+        let tile_atlas = tile::test_atlas(8);
+        let mut city = test_city(&tile_atlas);
+
+        run_days(&mut city, 45);
+        assert_eq!(city.day, 45);
+    }
+
+    #[test]
+    fn same_seed_gives_the_same_city() {
+        let tile_atlas = tile::test_atlas(8);
+        let mut first = test_city(&tile_atlas);
+        let mut second = test_city(&tile_atlas);
+
+        run_days(&mut first, 90);
+        run_days(&mut second, 90);
+
+        assert_eq!(first.population, second.population);
+        assert_eq!(first.funds, second.funds);
+        assert_eq!(first.employable, second.employable);
+    }
+
+    #[test]
+    fn population_grows_into_the_homes() {
+        let tile_atlas = tile::test_atlas(8);
+        let mut city = test_city(&tile_atlas);
+
+        run_days(&mut city, 120);
+
+        let homes = city.map.count_tiles().homes as f64;
+        assert!(city.population > 0.0, "nobody moved in after 120 days");
+        assert!(city.housed() <= homes, "{} people live in {} homes", city.housed(), homes);
+    }
+
+    #[test]
+    fn taxes_are_paid_out_every_month() {
+        let tile_atlas = tile::test_atlas(8);
+        let mut city = test_city(&tile_atlas);
+        city.funds = 10000.0;
+
+        //four payouts
+        run_days(&mut city, 120);
+
+        assert!(city.funds > 10000.0, "the funds went from 10000 to {}", city.funds);
+    }
+
+    fn population_at(city: &City, index: uint) -> f64 {
+        match city.map.tile(index) {
+            &(tile::Tile {tile_type: tile::Residential {population, ..}, ..}, _, _) => population,
+            _ => fail!("tile {} is not a home", index)
+        }
+    }
+
+    fn region_at(city: &City, index: uint) -> uint {
+        let &(ref tile, _, _) = city.map.tile(index);
+        tile.regions[0]
+    }
+
+    #[test]
+    fn moved_homes_keep_their_people_and_region() {
+        let tile_atlas = tile::test_atlas(8);
+        let mut city = test_city(&tile_atlas);
+        run_days(&mut city, 120);
+
+        //a home on row 4, and the grass between the shops and the industries on row 6
+        let from = range(40u, 45).find(|&index| population_at(&city, index) > 0.0).expect("nobody lives on row 4");
+        let to = 63;
+        let population = population_at(&city, from);
+
+        city.move_tile(from, to).unwrap();
+
+        //the labels may have been renumbered, so the home is compared with the road it's next to
+        let road_region = region_at(&city, 53);
+        assert!(road_region != 0);
+        assert_eq!(population_at(&city, to), population);
+        assert_eq!(region_at(&city, to), road_region);
+        assert_eq!(region_at(&city, from), 0);
+
+        let region: Vec<uint> = city.map.region_tiles(0, road_region).map(|&index| index).collect();
+        assert!(region.contains(&to));
+        assert!(!region.contains(&from));
+
+        //homes can only be moved onto grass
+        assert!(city.move_tile(to, 53).is_err());
+    }
+
+    #[test]
+    fn shops_are_supplied_after_the_first_day() {
+        let tile_atlas = tile::test_atlas(8);
+        let mut city = test_city(&tile_atlas);
+
+        //the commercial zones are at x 0 to 2 on row 6
+        for index in range(60u, 63) {
+            let &(ref tile, _, _) = city.map.tile(index);
+            assert!(!tile.tile_type.lacks_goods(), "tile {} looks starved before the first day", index);
+        }
+
+        run_days(&mut city, 1);
+
+        for index in range(60u, 63) {
+            match city.map.tile(index) {
+                &(tile::Tile {tile_type: tile::Commercial {supplied, ..}, ..}, _, _) => assert!(supplied),
+                _ => fail!("tile {} is not a shop", index)
+            }
+        }
+    }
+
+    fn tinted(city: &City, index: uint) -> bool {
+        let &(ref tile, _, _) = city.map.tile(index);
+        tile.tint.is_some()
+    }
+
+    #[test]
+    fn coverage_overlay_tints_the_gaps() {
+        let tile_atlas = tile::test_atlas(8);
+        let mut city = test_city(&tile_atlas);
+
+        //a home in the corner, away from the road
+        place(&mut city.map, &tile_atlas, 0, 0, "residential");
+        city.coverage_overlay = Some(RoadAccess);
+        city.tiles_changed();
+
+        assert!(tinted(&city, 0));
+        assert!(!tinted(&city, 40));
+        assert!(!tinted(&city, 50));
+
+        //the road reaches the map edge, so only the lone home lacks a connection
+        city.coverage_overlay = Some(OutsideConnection);
+        city.update_coverage_tints();
+        assert!(tinted(&city, 0));
+        assert!(!tinted(&city, 40));
+
+        city.coverage_overlay = None;
+        city.update_coverage_tints();
+        assert!(range(0u, 100).all(|index| !tinted(&city, index)));
+    }
+
+    #[test]
+    fn imports_are_charged_as_expenses() {
+        let tile_atlas = tile::test_atlas(8);
+        let mut city = test_city(&tile_atlas);
+        city.funds = 10000.0;
+
+        run_days(&mut city, 1);
+
+        //five new industries need at most one unit each, and the road reaches the map edge
+        assert!(city.expenses > 0.0, "nothing was imported");
+        assert!(city.expenses <= 5.0 * city.import_price, "imported ${} on the first day", city.expenses);
+        assert_eq!(city.funds, 10000.0);
+    }
+
+    #[test]
+    fn nothing_is_imported_without_money() {
+        let tile_atlas = tile::test_atlas(8);
+        let mut city = test_city(&tile_atlas);
+        city.funds = 0.0;
+
+        run_days(&mut city, 1);
+
+        assert_eq!(city.expenses, 0.0);
+    }
+
+    #[test]
+    fn an_all_grass_city_has_no_regions() {
+        let tile_atlas = tile::test_atlas(8);
+
+        let mut params = map::GenerationParams::new();
+        params.width = 10;
+        params.height = 10;
+        params.flat = true;
+
+        let mut map = map::Map::empty(8);
+        map.regenerate(SEED, &params, &tile_atlas);
+
+        let mut city = City::with_params(map, quiet_params());
+        city.seed_rng(SEED);
+        city.tiles_changed();
+
+        for index in range(0u, 100) {
+            assert_eq!(region_at(&city, index), 0);
+        }
+        assert_eq!(city.map.region_tiles(0, 1).count(), 0);
+        assert_eq!(city.map.region_tiles(0, 0).count(), 100);
+
+        run_days(&mut city, 60);
+        assert_eq!(city.population, 0.0);
+    }
+
+    #[test]
+    fn immigration_is_capped_by_the_free_homes() {
+        let tile_atlas = tile::test_atlas(8);
+
+        let mut params = map::GenerationParams::new();
+        params.width = 40;
+        params.height = 40;
+        params.flat = true;
+
+        let mut map = map::Map::empty(8);
+        map.regenerate(SEED, &params, &tile_atlas);
+
+        //rows of homes along the roads, with a band of jobs at the bottom
+        for y in range(0u, 40) {
+            for x in range(0u, 40) {
+                let name = if y % 3 == 1 {
+                    "road"
+                } else if y < 30 {
+                    "residential"
+                } else if x < 20 {
+                    "commercial"
+                } else {
+                    "industrial"
+                };
+                place(&mut map, &tile_atlas, x, y, name);
+            }
+        }
+
+        let mut city = City::with_params(map, quiet_params());
+        city.seed_rng(SEED);
+        city.tiles_changed();
+
+        run_days(&mut city, 1);
+
+        //nobody lived here before, so everyone counted after the first day moved in during it
+        assert!(city.population > 0.0, "nobody moved in");
+        assert!(city.population <= 1.0 + city.empty_homes * 0.1,
+            "{} people moved in with {} empty homes", city.population, city.empty_homes);
+    }
+
+    #[test]
+    fn land_cost_only_raises_the_price_of_building() {
+        let tile_atlas = tile::test_atlas(8);
+        let mut city = test_city(&tile_atlas);
+        city.land_cost = LinearLandCost(0.1);
+
+        let multiplier = city.land_cost_multiplier();
+        assert!(multiplier > 1.0);
+
+        for name in ["road", "residential", "commercial", "industrial", "airport"].iter() {
+            let tile = tile_atlas.find(name).unwrap();
+            assert_eq!(city.build_cost(tile), tile.build_cost() as f64 * multiplier);
+        }
+
+        for name in ["grass", "demolish"].iter() {
+            let tile = tile_atlas.find(name).unwrap();
+            assert_eq!(city.build_cost(tile), tile.build_cost() as f64);
+        }
+    }
+}
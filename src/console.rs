@@ -0,0 +1,106 @@
+use std::mem::replace;
+
+static CONSOLE_LINES_SHOWN: uint = 8;
+static CONSOLE_HISTORY: uint = 64;
+
+pub enum Command {
+    //set the funds to the amount
+    SetFunds(f64),
+    //add people to the population pool, waiting for homes
+    AddPopulation(f64),
+    //jump to the day
+    SetDay(uint),
+    //generate a new map from the seed
+    RegenerateMap(u32),
+    Help
+}
+
+//a typed line of input and the lines echoed back from the commands
+pub struct Console {
+    pub input: String,
+    lines: Vec<String>
+}
+
+impl Console {
+    pub fn new() -> Console {
+        Console {
+            input: String::new(),
+            lines: Vec::new()
+        }
+    }
+
+    pub fn type_char(&mut self, c: char) {
+        if !c.is_control() {
+            self.input.push_char(c);
+        }
+    }
+
+    pub fn erase_char(&mut self) {
+        self.input.pop_char();
+    }
+
+    //takes the current input, echoes it and tries to read it as a command
+    pub fn submit(&mut self) -> Option<Command> {
+        let line = replace(&mut self.input, String::new());
+        if line.as_slice().trim().is_empty() {
+            return None;
+        }
+
+        self.print(format!("> {}", line));
+
+        match parse_command(line.as_slice()) {
+            Ok(command) => Some(command),
+            Err(message) => {
+                self.print(message);
+                None
+            }
+        }
+    }
+
+    pub fn print(&mut self, line: String) {
+        self.lines.push(line);
+
+        while self.lines.len() > CONSOLE_HISTORY {
+            self.lines.remove(0);
+        }
+    }
+
+    //the last few lines, followed by the input line
+    pub fn visible_lines(&self) -> Vec<String> {
+        let start = if self.lines.len() > CONSOLE_LINES_SHOWN {
+            self.lines.len() - CONSOLE_LINES_SHOWN
+        } else {
+            0
+        };
+
+        let mut lines: Vec<String> = self.lines.slice_from(start).iter().map(|line| line.clone()).collect();
+        lines.push(format!("> {}_", self.input));
+        lines
+    }
+}
+
+//commands are on the form "funds 99999", "pop 500", "day 100", "seed 42 regen" or "help"
+fn parse_command(line: &str) -> Result<Command, String> {
+    let words: Vec<&str> = line.words().collect();
+
+    match words.as_slice() {
+        ["funds", amount] => from_str::<f64>(amount).map(|amount| SetFunds(amount)).ok_or(format!("Not an amount: {}", amount)),
+        ["pop", amount] => match from_str::<f64>(amount) {
+            Some(amount) if amount >= 0.0 => Ok(AddPopulation(amount)),
+            _ => Err(format!("Not a population: {}", amount))
+        },
+        ["day", day] => from_str::<uint>(day).map(|day| SetDay(day)).ok_or(format!("Not a day: {}", day)),
+        ["seed", seed, "regen"] => from_str::<u32>(seed).map(|seed| RegenerateMap(seed)).ok_or(format!("Not a seed: {}", seed)),
+        ["help"] => Ok(Help),
+        _ => Err(format!("Unknown command: {}", line.trim()))
+    }
+}
+
+pub fn help_lines() -> Vec<String> {
+    vec![
+        "funds <amount>: set the funds".to_string(),
+        "pop <amount>: add people looking for homes".to_string(),
+        "day <day>: jump to the day".to_string(),
+        "seed <seed> regen: generate a new map".to_string()
+    ]
+}
@@ -1,10 +1,14 @@
 use std::rc::Rc;
 use std::cell::RefCell;
+use std::cmp::min;
+use std::rand::{Rng, task_rng};
 
 use rsfml;
 use rsfml::window::event::{
     Closed,
     Resized,
+    KeyPressed,
+    TextEntered,
     MouseMoved,
     MouseWheelMoved,
     MouseButtonPressed,
@@ -12,6 +16,9 @@ use rsfml::window::event::{
     NoEvent
 };
 use rsfml::window::mouse;
+use rsfml::window::keyboard;
+use rsfml::window::keyboard::Key;
+use rsfml::graphics::{RenderWindow, Color, FloatRect, CircleShape};
 use rsfml::system::vector2::{ToVec, Vector2f, Vector2i};
 
 use game;
@@ -19,6 +26,63 @@ use tile;
 use map;
 use city;
 use gui;
+use records;
+use saves;
+use scenario;
+use console;
+use victory_state;
+
+static LOG_LENGTH: uint = 50;
+static LOG_LINES_SHOWN: uint = 10;
+
+static STARTING_FUNDS: f64 = 10_000.0;
+
+static AUTOSAVE_INTERVAL: uint = 30;
+static AUTOSAVE_SLOTS: uint = 3;
+
+static IDLE_DELAY: f32 = 5.0;
+static MAX_IDLE_SPEED: f32 = 8.0;
+
+static PAYOUT_WARNING_DAYS: uint = 5;
+
+//fraction of the remaining distance the view moves per second when recentering
+static RECENTER_SPEED: f32 = 8.0;
+
+static EDGE_SCROLL_MARGIN: i32 = 8;
+static EDGE_SCROLL_SPEED: f32 = 400.0;
+
+static DEMAND_BAR_HEIGHT: f32 = 24.0;
+
+static SCENARIO_PANEL_WIDTH: f32 = 256.0;
+
+static CONSOLE_WIDTH: f32 = 400.0;
+
+//info bar entries narrower than this get abbreviated text
+static COMPACT_ENTRY_WIDTH: f32 = 160.0;
+
+//short messages are stacked in the bottom right corner for a few seconds
+static NOTIFICATION_TIME: f32 = 4.0;
+static NOTIFICATION_WIDTH: f32 = 256.0;
+static MAX_NOTIFICATIONS: uint = 5;
+
+//each zoom step shows this much more or less of the map, unless it snaps to whole pixels
+static ZOOM_STEP: f32 = 1.25;
+static MIN_ZOOM: f32 = 0.125;
+static MAX_ZOOM: f32 = 8.0;
+
+//the most tiles the fill tool may select at once
+static FILL_LIMIT: uint = 400;
+
+//seconds for a delivery marker to reach the store, and how many can be on their way at once
+static DELIVERY_TIME: f32 = 1.5;
+static MAX_DELIVERY_MARKERS: uint = 64;
+
+//a good on its way from an industry to a store, moving in a straight line
+struct DeliveryMarker {
+    from: Vector2f,
+    to: Vector2f,
+    progress: f32
+}
 
 enum ActionState {
     Nothing,
@@ -33,15 +97,58 @@ pub struct EditState<'s> {
     action_state: ActionState,
     zoom_level: f32,
     current_tile: Option<tile::Tile>,
+    current_action: &'static str,
+    demolishing: bool,
+    setting_direction: bool,
+    analyzing: bool,
+    filling: bool,
+    routing: bool,
+    fast_forward_when_idle: bool,
+    idle_time: f32,
+    edge_scrolling: bool,
+    edge_scroll: Vector2f,
+    view_target: Option<Vector2f>,
+    selection_affordable: bool,
+    show_deliveries: bool,
+    hud_hidden: bool,
+    delivery_markers: Vec<DeliveryMarker>,
+    payout_warning: Option<String>,
+    warned_day: uint,
 
     right_click_menu: gui::Gui<'s, 'static, &'static str>,
+    load_menu: gui::Gui<'s, 'static, Path>,
+    menu_costs: Vec<Option<(&'static str, tile::Tile)>>,
     selection_cost_text: gui::Gui<'s, 'static, ()>,
     info_text: gui::Gui<'s, 'static, ()>,
-    info_bar: gui::Gui<'s, 'static, ()>
+    info_bar: gui::Gui<'s, 'static, ()>,
+    compact_info_bar: bool,
+    log_panel: gui::Gui<'s, 'static, ()>,
+    scenario_panel: gui::Gui<'s, 'static, ()>,
+    console_panel: gui::Gui<'s, 'static, ()>,
+    demand_bars: gui::BarChart<'s>,
+    delivery_shape: CircleShape<'s>,
+    notifications: gui::Notifications<'s>,
+
+    log: Vec<String>,
+    //how many of the newest lines are scrolled out of view
+    log_scroll: uint,
+
+    console: console::Console,
+
+    scenario: Option<scenario::Scenario>,
+    scenario_day: Option<uint>,
+    scenario_complete: bool,
+    victory_pending: bool,
+
+    saves: saves::SaveManager,
+    autosave_interval: uint,
+    autosave_slots: uint,
+    next_autosave_slot: uint,
+    last_autosave_day: uint
 }
 
 impl<'s> EditState<'s> {
-    pub fn new(game: &game::Game) -> Option<EditState<'s>> {
+    pub fn new(game: &game::Game, params: city::CityParams) -> Option<EditState<'s>> {
         let size = game.window.get_size().to_vector2f();
         let center = size.mul(&0.5f32);
 
@@ -50,41 +157,64 @@ impl<'s> EditState<'s> {
             None => return None
         };
 
-        let map = map::Map::new_generated(game.tile_size, &game.tile_atlas);
-
-        let (width, height) = map.size();
+        let map = map::Map::new_generated(game.tile_size, &map::GenerationParams::new(), &game.tile_atlas);
 
-        let mut city = city::City::new(map);
-        city.funds = 10_000.0;
+        let center = map.center();
 
-        let center = Vector2f::new(
-            (width * game.tile_size) as f32,
-            (height * game.tile_size) as f32 * 0.5
-        );
+        let mut city = city::City::with_params(map, params);
+        city.funds = STARTING_FUNDS;
+        city.regrowth_tile = game.tile_atlas.find(&"forest").map(|forest| forest.clone());
 
         let game_view = match rsfml::graphics::View::new_init(&center, &size) {
             Some(view) => view,
             None => return None
         };
 
+        let menu_items = [
+            ("Inspect", "inspect"),
+            ("Analyze", "analyze"),
+            ("Demolish", "demolish"),
+            ("Flatten", "grass"),
+            ("Forest", "forest"),
+            ("Residential (Low)", "residential_low"),
+            ("Residential Zone", "residential"),
+            ("Residential (High)", "residential_high"),
+            ("Commercial Zone", "commercial"),
+            ("Industrial Zone", "industrial"),
+            ("Road", "road"),
+            ("Airport", "airport"),
+            ("Road Direction", "road_direction"),
+            ("Fill Area", "fill_area"),
+            ("Auto Route", "road_route"),
+            ("New Map", "new_map"),
+            ("New Map (River)", "new_map_river"),
+            ("New Map (Flat)", "new_map_flat"),
+            ("Load City", "load_city")
+        ];
+
+        //the label and tile for each menu entry that builds something
+        let menu_costs: Vec<Option<(&'static str, tile::Tile)>> = menu_items.iter()
+            .map(|&(label, name)| game.tile_atlas.find_equiv(&name).map(|tile| (label, tile.clone())))
+            .collect();
+
         let right_click_menu = gui::Gui::new(
             Vector2f::new(196.0, 16.0), 2, false,
             game.stylesheets.find(&"button").unwrap().clone(),
-            vec![
-                ("Inspect".to_string(), "inspect"),
-                (format!("Flatten ${}", game.tile_atlas.find(&"grass").expect("grass tile was not loaded").cost), "grass"),
-                (format!("Forest ${}", game.tile_atlas.find(&"forest").expect("forest tile was not loaded").cost), "forest"),
-                (format!("Residential Zone ${}", game.tile_atlas.find(&"residential").expect("residential tile was not loaded").cost), "residential"),
-                (format!("Commercial Zone ${}", game.tile_atlas.find(&"commercial").expect("commercial tile was not loaded").cost), "commercial"),
-                (format!("Industrial Zone ${}", game.tile_atlas.find(&"industrial").expect("industrial tile was not loaded").cost), "industrial"),
-                (format!("Road ${}", game.tile_atlas.find(&"road").expect("road tile was not loaded").cost), "road")
-            ]
+            menu_items.iter().zip(menu_costs.iter()).map(|(&(label, name), cost)| match *cost {
+                Some((_, ref tile)) => (format!("{} ${}", label, tile.build_cost()), name),
+                None => (label.to_string(), name)
+            }).collect()
+        );
+
+        //filled with the saves when it's opened
+        let load_menu = gui::Gui::empty(
+            Vector2f::new(196.0, 16.0), 2, false,
+            game.stylesheets.find(&"button").unwrap().clone()
         );
 
-        let selection_cost_text = gui::Gui::new(
+        let selection_cost_text = gui::Gui::empty(
             Vector2f::new(196.0, 16.0), 0, false,
-            game.stylesheets.find(&"text").unwrap().clone(),
-            vec![("", ())]
+            game.stylesheets.find(&"text").unwrap().clone()
         );
 
         let mut info_bar = gui::Gui::new(
@@ -102,11 +232,45 @@ impl<'s> EditState<'s> {
         info_bar.transform.set_position(&info_bar_pos);
         info_bar.show();
 
-        let info_text = gui::Gui::new::<String>(
+        let info_text = gui::Gui::empty(
             Vector2f::new(196.0, 16.0), 2, false,
-            game.stylesheets.find(&"button").unwrap().clone(),
-            Vec::new()
+            game.stylesheets.find(&"button").unwrap().clone()
+        );
+
+        let log_panel = gui::Gui::empty(
+            Vector2f::new(320.0, 16.0), 2, false,
+            game.stylesheets.find(&"button").unwrap().clone()
+        );
+
+        //the objectives are listed in the top right corner
+        let mut scenario_panel = gui::Gui::empty(
+            Vector2f::new(SCENARIO_PANEL_WIDTH, 16.0), 2, false,
+            game.stylesheets.find(&"button").unwrap().clone()
+        );
+        scenario_panel.transform.set_position(&game.window.map_pixel_to_coords(&Vector2i::new(size.x as i32 - SCENARIO_PANEL_WIDTH as i32, 0), &gui_view));
+
+        let mut console_panel = gui::Gui::empty(
+            Vector2f::new(CONSOLE_WIDTH, 16.0), 2, false,
+            game.stylesheets.find(&"button").unwrap().clone()
+        );
+        console_panel.transform.set_position(&game.window.map_pixel_to_coords(&Vector2i::new((size.x - CONSOLE_WIDTH) as i32 / 2, 0), &gui_view));
+
+        let mut delivery_shape = CircleShape::new_init(2.0, 8).expect("unable to create delivery marker");
+        delivery_shape.set_fill_color(&Color::new_RGB(0xff, 0xe0, 0x40));
+        delivery_shape.set_origin(&Vector2f::new(2.0, 2.0));
+
+        let mut notifications = gui::Notifications::new(
+            NOTIFICATION_WIDTH, MAX_NOTIFICATIONS,
+            game.stylesheets.find(&"button").unwrap().clone()
+        );
+        notifications.set_corner(&game.window.map_pixel_to_coords(&Vector2i::new(size.x as i32, size.y as i32 - 20), &gui_view));
+
+        //residential, commercial and industrial, just above the info bar
+        let mut demand_bars = gui::BarChart::new(
+            [Color::new_RGB(0x00, 0xc0, 0x00), Color::new_RGB(0x00, 0x60, 0xff), Color::new_RGB(0xff, 0xc0, 0x00)],
+            8.0, DEMAND_BAR_HEIGHT
         );
+        demand_bars.set_position(&info_bar_pos.add(&Vector2f::new(4.0, -(DEMAND_BAR_HEIGHT + 4.0))));
 
         Some(EditState {
             game_view: Rc::new(RefCell::new(game_view)),
@@ -115,13 +279,458 @@ impl<'s> EditState<'s> {
             action_state: Nothing,
             zoom_level: 1.0,
             current_tile: None,
+            current_action: "inspect",
+            demolishing: false,
+            setting_direction: false,
+            analyzing: false,
+            filling: false,
+            routing: false,
+            fast_forward_when_idle: false,
+            idle_time: 0.0,
+            edge_scrolling: game.settings.edge_scrolling,
+            edge_scroll: Vector2f::new(0.0, 0.0),
+            view_target: None,
+            selection_affordable: true,
+            show_deliveries: true,
+            hud_hidden: false,
+            delivery_markers: Vec::new(),
+            payout_warning: None,
+            warned_day: 0,
 
             right_click_menu: right_click_menu,
+            load_menu: load_menu,
+            menu_costs: menu_costs,
             selection_cost_text: selection_cost_text,
             info_bar: info_bar,
-            info_text: info_text
+            compact_info_bar: size.x / 5.0 < COMPACT_ENTRY_WIDTH,
+            info_text: info_text,
+            log_panel: log_panel,
+            scenario_panel: scenario_panel,
+            console_panel: console_panel,
+            demand_bars: demand_bars,
+            delivery_shape: delivery_shape,
+            notifications: notifications,
+
+            log: Vec::new(),
+            log_scroll: 0,
+
+            console: console::Console::new(),
+
+            scenario: scenario::Scenario::load(&Path::new(scenario::SCENARIO_PATH)).ok(),
+            scenario_day: None,
+            scenario_complete: false,
+            victory_pending: false,
+
+            saves: saves::SaveManager::new(Path::new(saves::SAVE_DIRECTORY)),
+            autosave_interval: AUTOSAVE_INTERVAL,
+            autosave_slots: AUTOSAVE_SLOTS,
+            next_autosave_slot: 0,
+            last_autosave_day: 0
         })
     }
+
+    fn new_map(&mut self, game: &game::Game, params: &map::GenerationParams) {
+        let seed = [task_rng().gen(), task_rng().gen(), task_rng().gen(), task_rng().gen()];
+        self.new_map_from_seed(game, params, seed);
+    }
+
+    fn new_map_from_seed(&mut self, game: &game::Game, params: &map::GenerationParams, seed: [u32, ..4]) {
+        self.save_records();
+
+        self.city.map.regenerate(seed, params, &game.tile_atlas);
+        self.city.reset();
+        self.city.funds = STARTING_FUNDS;
+        self.city.tiles_changed();
+
+        self.action_state = Nothing;
+        self.current_tile = None;
+        self.current_action = "inspect";
+        self.demolishing = false;
+        self.setting_direction = false;
+        self.analyzing = false;
+        self.last_autosave_day = 0;
+        self.warned_day = 0;
+        self.delivery_markers.clear();
+
+        match self.scenario {
+            Some(ref mut scenario) => scenario.restart(),
+            None => {}
+        }
+        self.scenario_day = None;
+        self.scenario_complete = false;
+        self.info_text.hide();
+        self.selection_cost_text.hide();
+    }
+
+    fn choose_action(&mut self, game: &game::Game, action: Option<&'static str>) {
+        match action {
+            Some("new_map") => self.new_map(game, &map::GenerationParams::new()),
+            Some("new_map_river") => {
+                let mut params = map::GenerationParams::new();
+                params.river = true;
+                self.new_map(game, &params);
+            },
+            Some("new_map_flat") => {
+                let mut params = map::GenerationParams::new();
+                params.flat = true;
+                self.new_map(game, &params);
+            },
+            Some("inspect") => {
+                self.current_tile = None;
+                self.current_action = "inspect";
+                self.demolishing = false;
+                self.setting_direction = false;
+                self.analyzing = false;
+            },
+            Some("analyze") => {
+                self.current_tile = None;
+                self.current_action = "analyze";
+                self.demolishing = false;
+                self.setting_direction = false;
+                self.analyzing = true;
+            },
+            Some("load_city") => self.open_load_menu(game),
+            Some("fill_area") => self.filling = !self.filling,
+            Some("road_route") => self.routing = !self.routing,
+            Some("road_direction") => {
+                self.current_tile = None;
+                self.current_action = "road_direction";
+                self.demolishing = false;
+                self.setting_direction = true;
+                self.analyzing = false;
+            },
+            Some(tile_name) => {
+                self.current_tile = Some(game.tile_atlas.find_equiv(&tile_name).expect("unknown tile").clone());
+                self.current_action = tile_name;
+                self.demolishing = tile_name == "demolish";
+                self.setting_direction = false;
+                self.analyzing = false;
+            },
+            None => {}
+        }
+    }
+
+    //lists the saved cities where the right click menu was
+    fn open_load_menu(&mut self, game: &game::Game) {
+        let saves = self.saves.list_saves();
+        if saves.is_empty() {
+            self.notify("There are no saved cities".to_string());
+            return;
+        }
+
+        let entries = saves.move_iter().map(|path| (path.filename_display().to_string(), path)).collect();
+        self.load_menu.set_entries(entries);
+
+        let anchor = self.right_click_menu.transform.get_position();
+        self.load_menu.place_near(&anchor, &game.window.get_size().to_vector2f(), 0.0);
+        self.load_menu.show();
+    }
+
+    fn load_city(&mut self, game: &game::Game, path: &Path) {
+        self.save_records();
+
+        let message = match self.saves.load_path(&mut self.city, path, &game.tile_atlas) {
+            Ok(()) => {
+                self.action_state = Nothing;
+                self.last_autosave_day = self.city.day;
+                self.warned_day = self.city.day;
+                self.delivery_markers.clear();
+                self.info_text.hide();
+                self.selection_cost_text.hide();
+                format!("Loaded {}", path.display())
+            },
+            Err(e) => format!("Could not load: {}", e)
+        };
+        self.notify(message);
+    }
+
+    //lets the open popups have a left click first, and returns true if it shouldn't reach the map
+    fn click_popups(&mut self, game: &game::Game, gui_pos: &Vector2f) -> bool {
+        if self.load_menu.visible() {
+            if self.load_menu.contains_point(gui_pos) {
+                let path = self.load_menu.activate_at(gui_pos).map(|path| path.clone());
+                match path {
+                    Some(path) => self.load_city(game, &path),
+                    None => {}
+                }
+            }
+
+            self.load_menu.hide();
+            return true;
+        }
+
+        if self.right_click_menu.visible() {
+            //clicking outside the menu only closes it
+            if self.right_click_menu.contains_point(gui_pos) {
+                let action = self.right_click_menu.activate_at(gui_pos).map(|&name| name);
+                self.choose_action(game, action);
+            }
+
+            self.right_click_menu.hide();
+            return true;
+        }
+
+        self.info_text.contains_point(gui_pos)
+    }
+
+    //checks the objectives once per day
+    fn update_scenario(&mut self) {
+        if self.scenario_day == Some(self.city.day) {
+            return;
+        }
+        self.scenario_day = Some(self.city.day);
+
+        let lines = match self.scenario {
+            Some(ref mut scenario) => {
+                scenario.update(&self.city);
+
+                if scenario.is_won() && !self.scenario_complete {
+                    self.scenario_complete = true;
+                    self.victory_pending = true;
+                }
+
+                scenario.describe(&self.city)
+            },
+            None => return
+        };
+
+        self.scenario_panel.set_entries(lines.move_iter().map(|line| (line, ())).collect());
+        self.scenario_panel.show();
+    }
+
+    fn autosave(&mut self) {
+        let day = self.city.day;
+        if self.autosave_interval == 0 || day == self.last_autosave_day || day % self.autosave_interval != 0 {
+            return;
+        }
+
+        self.last_autosave_day = day;
+
+        match self.saves.autosave(&self.city, self.next_autosave_slot) {
+            Ok(_) => self.next_autosave_slot = (self.next_autosave_slot + 1) % self.autosave_slots,
+            Err(e) => {
+                self.push_log(format!("Day {}: Autosave failed: {}", day, e));
+                self.refresh_log();
+            }
+        }
+    }
+
+    fn push_log(&mut self, line: String) {
+        self.log.push(line);
+
+        //a scrolled log keeps showing the same lines
+        if self.log_scroll > 0 {
+            self.log_scroll += 1;
+        }
+
+        while self.log.len() > LOG_LENGTH {
+            self.log.remove(0);
+        }
+    }
+
+    fn refresh_log(&mut self) {
+        let (start, end) = log_window(self.log.len(), self.log_scroll, LOG_LINES_SHOWN);
+        self.log_scroll = self.log.len() - end;
+
+        let entries: Vec<(String, ())> = self.log.slice(start, end).iter().map(|line| (line.clone(), ())).collect();
+        self.log_panel.set_entries(entries);
+
+        if self.log_panel.visible() {
+            self.log_panel.show();
+        }
+    }
+
+    //positive steps scroll back to older lines
+    fn scroll_log(&mut self, steps: int) {
+        self.log_scroll = if steps < 0 {
+            self.log_scroll.saturating_sub((-steps) as uint)
+        } else {
+            self.log_scroll + steps as uint
+        };
+        self.refresh_log();
+    }
+
+    fn refresh_console(&mut self) {
+        let entries: Vec<(String, ())> = self.console.visible_lines().move_iter().map(|line| (line, ())).collect();
+        self.console_panel.set_entries(entries);
+
+        if self.console_panel.visible() {
+            self.console_panel.show();
+        }
+    }
+
+    fn run_console_command(&mut self, game: &game::Game) {
+        let command = match self.console.submit() {
+            Some(command) => command,
+            None => return
+        };
+
+        match command {
+            console::SetFunds(amount) => {
+                self.city.funds = amount;
+                self.console.print(format!("Funds set to ${:.0}", amount));
+            },
+            console::AddPopulation(amount) => {
+                self.city.add_people(amount);
+                self.console.print(format!("{:.0} people are looking for homes", amount));
+            },
+            console::SetDay(day) => {
+                self.city.day = day;
+                self.console.print(format!("Jumped to day {}", day));
+            },
+            console::RegenerateMap(seed) => {
+                //the generator can't start from an all zero seed, so the rest is fixed
+                self.new_map_from_seed(game, &map::GenerationParams::new(), [seed, 362436069, 521288629, 88675123]);
+                self.console.print(format!("Generated a new map from seed {}", seed));
+            },
+            console::Help => for line in console::help_lines().move_iter() {
+                self.console.print(line);
+            }
+        }
+    }
+
+    //narrow entries only get the main numbers, with short labels
+    fn refresh_info_bar(&mut self) {
+        let compact = self.compact_info_bar;
+
+        let event = self.city.active_events.iter().find(|event| event.kind != city::Fire).map(|event| event.kind.clone());
+        match (event, compact) {
+            (_, true) => self.info_bar.set_entry_text(0, format!("D {}", self.city.day)),
+            (Some(kind), false) => self.info_bar.set_entry_text(0, format!("Day: {} ({})", self.city.day, kind)),
+            (None, false) => self.info_bar.set_entry_text(0, format!("Day: {}", self.city.day))
+        }
+
+        if compact {
+            self.info_bar.set_entry_text(1, format!("${:.0}", self.city.funds));
+            self.info_bar.set_entry_text(2, format!("Pop {:.0}", self.city.population));
+            self.info_bar.set_entry_text(3, format!("Wrk {:.0}", self.city.employable));
+        } else {
+            self.info_bar.set_entry_text(1, format!("${:.0} (goods ${:.0})", self.city.funds, self.city.goods_price));
+            self.info_bar.set_entry_text(2, format!("{:.0} ({:.0})", self.city.population, self.city.homeless()));
+            self.info_bar.set_entry_text(3, format!("{:.0} ({:.0})", self.city.employable, self.city.unemployed()));
+        }
+
+        let action_name = if self.demolishing {
+            "Demolish".to_string()
+        } else if self.setting_direction {
+            if compact { "Direction".to_string() } else { "Road Direction".to_string() }
+        } else if self.analyzing {
+            "Analyze".to_string()
+        } else {
+            match self.current_tile {
+                Some(ref tile) if self.filling && !compact => format!("{} (Fill)", tile.tile_type),
+                Some(ref tile) if self.routing && !compact && tile.tile_type.similar_to(&tile::Road) => format!("{} (Route)", tile.tile_type),
+                Some(ref tile) => tile.tile_type.to_string(),
+                None => "Inspect".to_string()
+            }
+        };
+
+        if compact {
+            self.info_bar.set_entry_text(4, action_name);
+        } else {
+            self.info_bar.set_entry_text(4, format!("{} {:.0}%", action_name, 100.0 / self.zoom_level));
+        }
+    }
+
+    fn zoom_at(&mut self, window: &RenderWindow, zoom_out: bool, pixel_zoom: bool) {
+        //keep the world position under the cursor in place while zooming
+        let mouse_pos = window.get_mouse_position();
+        let before = window.map_pixel_to_coords(&mouse_pos, self.game_view.borrow().deref());
+
+        self.zoom_level = next_zoom(self.zoom_level, zoom_out, pixel_zoom);
+        let window_size = window.get_size().to_vector2f();
+        self.game_view.borrow_mut().set_size(&window_size.mul(&self.zoom_level));
+
+        let after = window.map_pixel_to_coords(&mouse_pos, self.game_view.borrow().deref());
+        self.game_view.borrow_mut().move(&before.sub(&after));
+    }
+
+    //the game view is sized just like when zooming. the gui view also has to be
+    //recentered, or it would shrink or grow around its old center and drift off
+    fn resize_views(&mut self, window_size: &Vector2f) {
+        let mut game_view = self.game_view.borrow_mut();
+        game_view.set_size(&window_size.mul(&self.zoom_level));
+        game_view.set_viewport(&FloatRect::new(0.0, 0.0, 1.0, 1.0));
+
+        //the gui is kept in pixels, with the origin in the top left corner
+        let mut gui_view = self.gui_view.borrow_mut();
+        gui_view.set_size(window_size);
+        gui_view.set_center(&window_size.mul(&0.5f32));
+        gui_view.set_viewport(&FloatRect::new(0.0, 0.0, 1.0, 1.0));
+    }
+
+    fn apply_stylesheets(&mut self, game: &game::Game) {
+        let button = game.stylesheets.find(&"button").unwrap();
+        let text = game.stylesheets.find(&"text").unwrap();
+
+        self.right_click_menu.set_style(button.clone());
+        self.load_menu.set_style(button.clone());
+        self.selection_cost_text.set_style(text.clone());
+        self.info_bar.set_style(button.clone());
+        self.info_text.set_style(button.clone());
+        self.log_panel.set_style(button.clone());
+        self.scenario_panel.set_style(button.clone());
+        self.console_panel.set_style(button.clone());
+        self.notifications.set_style(button.clone());
+    }
+
+    //every gui element of the state, in drawing order, so they can all be hidden at once
+    fn draw_hud(&self, window: &mut RenderWindow) {
+        window.draw(&self.info_bar);
+        window.draw(&self.demand_bars);
+        window.draw(&self.right_click_menu);
+        window.draw(&self.load_menu);
+        window.draw(&self.selection_cost_text);
+        window.draw(&self.info_text);
+        window.draw(&self.log_panel);
+        window.draw(&self.scenario_panel);
+        window.draw(&self.console_panel);
+        window.draw(&self.notifications);
+    }
+
+    fn cursor_at(&self, gui_pos: &Vector2f) -> uint {
+        if self.load_menu.visible() {
+            return game::CURSOR_POINTER;
+        }
+
+        if self.right_click_menu.visible() {
+            return match self.right_click_menu.get_entry(gui_pos) {
+                Some(index) if !self.right_click_menu.entries[index].enabled => game::CURSOR_BLOCKED,
+                _ => game::CURSOR_POINTER
+            };
+        }
+
+        match self.action_state {
+            Panning(_) => game::CURSOR_PAN,
+            Selecting(..) if !self.selection_affordable => game::CURSOR_BLOCKED,
+            _ if self.current_tile.is_some() || self.setting_direction => game::CURSOR_BUILD,
+            _ => game::CURSOR_POINTER
+        }
+    }
+
+    fn notify(&mut self, message: String) {
+        self.notifications.push(message, NOTIFICATION_TIME);
+    }
+
+    //shows the error of something that happened in the background, if there was one
+    fn report(&mut self, result: Result<(), String>) {
+        match result {
+            Err(message) => self.notify(message),
+            Ok(()) => {}
+        }
+    }
+
+    fn save_records(&mut self) {
+        let path = Path::new(records::RECORDS_PATH);
+        let mut records = records::Records::load(&path).unwrap_or_else(|_| records::Records::new());
+
+        if records.update(self.city.peak_population, self.city.peak_funds) {
+            match records.save(&path) {
+                Err(e) => self.notify(format!("Could not save records: {}", saves::describe_error(&e))),
+                Ok(()) => {}
+            }
+        }
+    }
 }
 
 impl<'s> game::GameState for EditState<'s> {
@@ -131,52 +740,333 @@ impl<'s> game::GameState for EditState<'s> {
         game.window.draw(&game.background);
 
         game.window.set_view(self.game_view.clone());
-        self.city.map.draw(&mut game.window, dt);
+        //let the animations follow the simulation when it's sped up
+        let animation_dt = if game.settings.sync_animations { dt * self.city.speed() } else { dt };
+        self.city.map.draw(&mut game.window, animation_dt, game.settings.animate_tiles);
+
+        for marker in self.delivery_markers.iter() {
+            self.delivery_shape.set_position(&marker.from.add(&marker.to.sub(&marker.from).mul(&marker.progress)));
+            game.window.draw(&self.delivery_shape);
+        }
 
         game.window.set_view(self.gui_view.clone());
-        game.window.draw(&self.info_bar);
-        game.window.draw(&self.right_click_menu);
-        game.window.draw(&self.selection_cost_text);
-        game.window.draw(&self.info_text);
+        if !self.hud_hidden {
+            self.draw_hud(&mut game.window);
+        }
+
+        let mouse_pos = game.window.map_pixel_to_coords(&game.window.get_mouse_position(), self.gui_view.borrow().deref());
+        let cursor = self.cursor_at(&mouse_pos);
+        game.draw_cursor(cursor, &mouse_pos);
     }
 
     fn update(&mut self, dt: f32) {
+        self.idle_time += dt;
+        self.notifications.update(dt);
+
+        //speed up gradually after a while without any input
+        if self.fast_forward_when_idle && self.idle_time > IDLE_DELAY {
+            let speed = (1.0 + (self.idle_time - IDLE_DELAY) * 0.5).min(MAX_IDLE_SPEED);
+            self.city.set_speed(speed);
+        } else {
+            self.city.set_speed(1.0);
+        }
+
+        match self.view_target {
+            Some(target) => {
+                let center = self.game_view.borrow().get_center();
+                let remaining = target.sub(&center);
+
+                if remaining.x.abs() < 0.5 && remaining.y.abs() < 0.5 {
+                    self.game_view.borrow_mut().set_center(&target);
+                    self.view_target = None;
+                } else {
+                    let step = remaining.mul(&(dt * RECENTER_SPEED).min(1.0));
+                    self.game_view.borrow_mut().move(&step);
+                }
+            },
+            None => {}
+        }
+
+        match self.action_state {
+            Nothing if self.edge_scrolling => {
+                let offset = self.edge_scroll.mul(&(EDGE_SCROLL_SPEED * dt * self.zoom_level));
+                self.game_view.borrow_mut().move(&offset);
+            },
+            _ => {}
+        }
+
         self.city.update(dt);
-        self.info_bar.set_entry_text(0, format!("Day: {}", self.city.day));
-        self.info_bar.set_entry_text(1, format!("${:.0}", self.city.funds));
-        self.info_bar.set_entry_text(2, format!("{:.0} ({:.0})", self.city.population, self.city.get_homeless()));
-        self.info_bar.set_entry_text(3, format!("{:.0} ({:.0})", self.city.employable, self.city.get_unemployed()));
-        let action_name = self.current_tile.as_ref().map(|tile| tile.tile_type.to_string()).unwrap_or_else(|| "Inspect".to_string());
-        self.info_bar.set_entry_text(4, action_name);
+        self.autosave();
+
+        self.refresh_info_bar();
+
+        //the prices follow the land cost as the city grows
+        for (index, cost) in self.menu_costs.iter().enumerate() {
+            match *cost {
+                Some((label, ref tile)) => {
+                    let cost = self.city.build_cost(tile);
+                    self.right_click_menu.set_entry_text(index, format!("{} ${:.0}", label, cost));
+                    self.right_click_menu.set_entry_enabled(index, cost <= self.city.funds);
+                },
+                None => {}
+            }
+        }
+
+        let (residential, commercial, industrial) = self.city.demand();
+        self.demand_bars.set_values([residential, commercial, industrial]);
+
+        //warn once per month when the coming payout would leave the city in debt
+        let days_left = self.city.days_until_payout();
+        let projected = self.city.projected_monthly_net();
+        if days_left <= PAYOUT_WARNING_DAYS && self.city.funds + projected < 0.0 && self.city.day >= self.warned_day + PAYOUT_WARNING_DAYS {
+            self.payout_warning = Some(format!("Funds will not cover ${:.0} in {} days", -projected, days_left));
+            self.warned_day = self.city.day;
+        }
+
+        self.update_scenario();
+
+        for marker in self.delivery_markers.mut_iter() {
+            marker.progress += dt / DELIVERY_TIME;
+        }
+        self.delivery_markers.retain(|marker| marker.progress < 1.0);
+
+        for (from, to) in self.city.drain_deliveries().move_iter() {
+            if self.show_deliveries && self.delivery_markers.len() < MAX_DELIVERY_MARKERS {
+                self.delivery_markers.push(DeliveryMarker {
+                    from: self.city.map.tile_center(from),
+                    to: self.city.map.tile_center(to),
+                    progress: 0.0
+                });
+            }
+        }
+
+        let events = self.city.drain_events();
+        if !events.is_empty() {
+            for event in events.iter() {
+                self.push_log(event.to_string());
+            }
+
+            self.refresh_log();
+        }
     }
 
     fn handle_input(&mut self, game: &mut game::Game) {
         let game_pos = game.window.map_pixel_to_coords(&game.window.get_mouse_position(), self.game_view.borrow().deref());
         let gui_pos = game.window.map_pixel_to_coords(&game.window.get_mouse_position(), self.gui_view.borrow().deref());
 
-        let index = self.right_click_menu.get_entry(&gui_pos);
-        self.right_click_menu.highlight(index);
+        if self.victory_pending {
+            self.victory_pending = false;
+            match victory_state::VictoryState::new(game, self.city.day, self.city.population) {
+                Some(state) => game.push_state(box state as Box<game::GameState>),
+                None => {}
+            }
+            return;
+        }
+
+        self.edge_scroll = edge_scroll_direction(&game.window.get_mouse_position(), &game.window.get_size().to_vector2f());
+
+        match self.payout_warning.take() {
+            Some(warning) => self.notify(warning),
+            None => {}
+        }
+
+        //hovering takes over the focus, otherwise keep whatever the keyboard focused
+        match self.right_click_menu.get_entry(&gui_pos) {
+            Some(index) => self.right_click_menu.focus(Some(index)),
+            None => {}
+        }
 
         loop {
-            match game.window.poll_event() {
-                Closed => game.window.close(),
+            let event = game.window.poll_event();
+
+            match event {
+                NoEvent => {},
+                _ => self.idle_time = 0.0
+            }
+
+            match event {
+                Closed => game.request_quit(),
+                //the console takes all typed input while it's open
+                TextEntered {code} if self.console_panel.visible() => if code != '`' {
+                    self.console.type_char(code);
+                    self.refresh_console();
+                },
+                KeyPressed {code, ..} if self.console_panel.visible() => {
+                    if game.key_bindings.is_bound("console", code) || game.key_bindings.is_bound("cancel", code) {
+                        self.console_panel.hide();
+                    } else if code == keyboard::Return {
+                        self.run_console_command(game);
+                        self.refresh_console();
+                    } else if code == keyboard::BackSpace {
+                        self.console.erase_char();
+                        self.refresh_console();
+                    }
+                },
+                KeyPressed {code, ..} if game.key_bindings.is_bound("console", code) => {
+                    self.refresh_console();
+                    self.console_panel.show();
+                },
                 Resized {width, height} => {
-                    let size = Vector2f::new(width as f32, height as f32);
-                    self.game_view.borrow_mut().set_size(&size);
-                    self.game_view.borrow_mut().zoom(self.zoom_level);
-                    self.gui_view.borrow_mut().set_size(&size);
-
-                    let info_bar_pos = game.window.map_pixel_to_coords(&Vector2i::new(0, height as i32 - 16), self.gui_view.borrow().deref());
-                    let info_bar_len = self.info_bar.entries.len() as f32;
-                    self.info_bar.set_dimensions(&Vector2f::new(width as f32 / info_bar_len, 16.0));
+                    self.resize_views(&Vector2f::new(width as f32, height as f32));
+
+                    self.info_bar.fit_width(width as f32);
+                    self.compact_info_bar = self.info_bar.entry_dimensions().x < COMPACT_ENTRY_WIDTH;
+                    self.refresh_info_bar();
+                    let info_bar_height = self.info_bar.measure().y as i32;
+                    let info_bar_pos = game.window.map_pixel_to_coords(&Vector2i::new(0, height as i32 - info_bar_height), self.gui_view.borrow().deref());
                     self.info_bar.transform.set_position(&info_bar_pos);
                     self.info_bar.show();
+                    let scenario_pos = game.window.map_pixel_to_coords(&Vector2i::new(width as i32 - SCENARIO_PANEL_WIDTH as i32, 0), self.gui_view.borrow().deref());
+                    self.scenario_panel.transform.set_position(&scenario_pos);
+                    if self.scenario.is_some() {
+                        self.scenario_panel.show();
+                    }
+                    let console_pos = game.window.map_pixel_to_coords(&Vector2i::new((width as i32 - CONSOLE_WIDTH as i32) / 2, 0), self.gui_view.borrow().deref());
+                    self.console_panel.transform.set_position(&console_pos);
+                    if self.console_panel.visible() {
+                        self.console_panel.show();
+                    }
+                    let notification_corner = game.window.map_pixel_to_coords(&Vector2i::new(width as i32, height as i32 - info_bar_height - 4), self.gui_view.borrow().deref());
+                    self.notifications.set_corner(&notification_corner);
+                    self.demand_bars.set_position(&info_bar_pos.add(&Vector2f::new(4.0, -(DEMAND_BAR_HEIGHT + 4.0))));
 
                     let background_size = game.background.get_texture().unwrap().borrow().get_size();
                     let gui_origin = game.window.map_pixel_to_coords(&Vector2i::new(0, 0), self.gui_view.borrow().deref());
                     game.background.set_position(&gui_origin);
                     game.background.set_scale(&Vector2f::new(width as f32 / background_size.x as f32, height as f32 / background_size.y as f32));
                 },
+                //an open menu is closed before cancelling anything below it
+                KeyPressed {code, ..} if self.load_menu.visible() && game.key_bindings.is_bound("cancel", code) => self.load_menu.hide(),
+                KeyPressed {code, ..} if self.right_click_menu.visible() && game.key_bindings.is_bound("cancel", code) => self.right_click_menu.hide(),
+                KeyPressed {code, ..} if game.key_bindings.is_bound("cancel", code) => match self.action_state {
+                    Selecting(..) => {
+                        self.action_state = Nothing;
+                        self.city.map.clear_selected();
+                        self.selection_cost_text.hide();
+                    },
+                    _ => {}
+                },
+                KeyPressed {code, ..} if game.key_bindings.is_bound("quick_save", code) => {
+                    let message = match self.saves.save(&self.city, 0) {
+                        Ok(path) => format!("Saved to {}", path.display()),
+                        Err(e) => format!("Could not save: {}", e)
+                    };
+                    self.notify(message);
+                },
+                KeyPressed {code, ..} if game.key_bindings.is_bound("quick_load", code) => {
+                    let path = self.saves.slot_path(0);
+                    self.load_city(game, &path);
+                },
+                KeyPressed {code, ..} if game.key_bindings.is_bound("toggle_log", code) => if self.log_panel.visible() {
+                    self.log_panel.hide();
+                } else {
+                    let log_pos = game.window.map_pixel_to_coords(&Vector2i::new(0, 0), self.gui_view.borrow().deref());
+                    self.log_panel.transform.set_position(&log_pos);
+                    self.log_panel.show();
+                },
+                KeyPressed {code: keyboard::PageUp, ..} if self.log_panel.visible() => self.scroll_log(LOG_LINES_SHOWN as int),
+                KeyPressed {code: keyboard::PageDown, ..} if self.log_panel.visible() => self.scroll_log(-(LOG_LINES_SHOWN as int)),
+                KeyPressed {code, ..} if game.key_bindings.is_bound("fast_forward", code) => self.fast_forward_when_idle = !self.fast_forward_when_idle,
+                KeyPressed {code, ctrl, ..} if bookmark_slot(code).is_some() => {
+                    let slot = bookmark_slot(code).unwrap();
+                    if ctrl {
+                        let center = self.game_view.borrow().get_center();
+                        *self.city.bookmarks.get_mut(slot) = Some(center);
+                        self.push_log(format!("Day {}: Bookmark {} set", self.city.day, slot));
+                        self.refresh_log();
+                    } else {
+                        match self.city.bookmarks[slot] {
+                            Some(center) => self.view_target = Some(center),
+                            None => {}
+                        }
+                    }
+                },
+                KeyPressed {code, ..} if game.key_bindings.is_bound("recenter", code) => {
+                    self.view_target = Some(self.city.map.center());
+                },
+                KeyPressed {code, ..} if game.key_bindings.is_bound("edge_scrolling", code) => {
+                    self.edge_scrolling = !self.edge_scrolling;
+                    game.settings.edge_scrolling = self.edge_scrolling;
+                    self.report(game.save_settings());
+                },
+                //the panels keep their own visibility, so showing the hud again brings back exactly what was there
+                KeyPressed {code, ..} if game.key_bindings.is_bound("toggle_hud", code) => self.hud_hidden = !self.hud_hidden,
+                KeyPressed {code, ..} if game.key_bindings.is_bound("deliveries", code) => {
+                    self.show_deliveries = !self.show_deliveries;
+                    if !self.show_deliveries {
+                        self.delivery_markers.clear();
+                    }
+                },
+                KeyPressed {code, ..} if game.key_bindings.is_bound("pixel_zoom", code) => {
+                    game.settings.pixel_zoom = !game.settings.pixel_zoom;
+                    self.report(game.save_settings());
+
+                    //snap right away, so the tiles are crisp without having to zoom first
+                    if game.settings.pixel_zoom {
+                        self.zoom_level = snap_zoom(self.zoom_level);
+                        let window_size = game.window.get_size().to_vector2f();
+                        self.game_view.borrow_mut().set_size(&window_size.mul(&self.zoom_level));
+                    }
+                },
+                KeyPressed {code, ..} if game.key_bindings.is_bound("toggle_animations", code) => {
+                    game.settings.animate_tiles = !game.settings.animate_tiles;
+                    self.report(game.save_settings());
+                },
+                KeyPressed {code, ..} if game.key_bindings.is_bound("sync_animations", code) => {
+                    game.settings.sync_animations = !game.settings.sync_animations;
+                    self.report(game.save_settings());
+                },
+                KeyPressed {code, ..} if game.key_bindings.is_bound("cycle_theme", code) => {
+                    self.report(game.cycle_theme());
+                    self.apply_stylesheets(game);
+                },
+                KeyPressed {code, ..} if game.key_bindings.is_bound("reset_taxes", code) => {
+                    self.city.reset_taxes();
+                    let day = self.city.day;
+                    self.push_log(format!("Day {}: Taxes reset to {:.0}%", day, self.city.residential_tax * 100.0));
+                    self.refresh_log();
+                },
+                KeyPressed {code, ..} if game.key_bindings.is_bound("auto_taxes", code) => {
+                    self.city.auto_balance_taxes = !self.city.auto_balance_taxes;
+                    let day = self.city.day;
+                    let state = if self.city.auto_balance_taxes { "on" } else { "off" };
+                    self.push_log(format!("Day {}: Automatic taxes {}", day, state));
+                    self.refresh_log();
+                },
+                KeyPressed {code, ..} if game.key_bindings.is_bound("goods_overlay", code) => self.city.map.overlay = match self.city.map.overlay {
+                    map::GoodsOverlay => map::NoOverlay,
+                    _ => map::GoodsOverlay
+                },
+                KeyPressed {code, ..} if game.key_bindings.is_bound("coverage_overlay", code) => {
+                    self.city.coverage_overlay = city::Service::next(self.city.coverage_overlay.clone());
+                    self.city.update_coverage_tints();
+
+                    match self.city.coverage_overlay {
+                        Some(ref service) => {
+                            self.info_text.set_entries(vec![(format!("Missing: {}", service), ())]);
+                            self.info_text.place_near(&gui_pos, &game.window.get_size().to_vector2f(), 16.0);
+                            self.info_text.show();
+                        },
+                        None => self.info_text.hide()
+                    }
+                },
+                KeyPressed {code, ..} if self.load_menu.visible() => {
+                    let path = self.load_menu.handle_key(code).map(|path| path.clone());
+                    match path {
+                        Some(path) => {
+                            self.load_menu.hide();
+                            self.load_city(game, &path);
+                        },
+                        None => {}
+                    }
+                },
+                KeyPressed {code, ..} if self.right_click_menu.visible() => {
+                    let action = self.right_click_menu.handle_key(code).map(|&name| name);
+                    if action.is_some() {
+                        self.choose_action(game, action);
+                        self.right_click_menu.hide();
+                    }
+                },
                 MouseMoved {x, y} => match self.action_state {
                     Panning(ref mut anchor) => {
                         let pos = Vector2f::new(anchor.x - x as f32, anchor.y - y as f32);
@@ -186,42 +1076,51 @@ impl<'s> game::GameState for EditState<'s> {
                     Selecting(ref selection_start, ref mut selection_end) => {
                         match self.current_tile {
                             Some(ref current_tile) => {
-                                let (width, _) = self.city.map.size();
-                                selection_end.x = (game_pos.y / game.tile_size as f32 + game_pos.x / (2.0 * game.tile_size as f32) - width as f32 * 0.5 - 0.5) as i32;
-                                selection_end.y = (game_pos.y / game.tile_size as f32 - game_pos.x / (2.0 * game.tile_size as f32) + width as f32 * 0.5 + 0.5) as i32;
+                                let new_end = self.city.map.screen_to_tile(&game_pos);
 
-                                self.city.map.clear_selected();
-                                if current_tile.tile_type.similar_to(&tile::Grass) {
-                                    self.city.map.select(selection_start.clone(), selection_end.clone(), |tile| tile.similar_to(&tile::Water));
-                                } else {
-                                    self.city.map.select(selection_start.clone(), selection_end.clone(), |tile| match tile {
-                                        &tile::Water | &tile::Forest | &tile::Road | &tile::Residential {..} | &tile::Commercial {..} | &tile::Industrial{..} => true,
-                                        tile if current_tile.tile_type.similar_to(tile) => true,
-                                        _ => false
-                                    });
+                                //only redo the selection when the cursor moves to another tile
+                                if new_end.x != selection_end.x || new_end.y != selection_end.y || self.city.map.num_selected == 0 {
+                                    *selection_end = new_end;
+
+                                    self.city.map.clear_selected();
+                                    if self.demolishing {
+                                        self.city.map.select(selection_start.clone(), selection_end.clone(), |tile| !tile.can_demolish());
+                                    } else {
+                                        let blacklisted = |tile: &tile::TileType| !current_tile.tile_type.can_build_over(tile);
+                                        let footprint = current_tile.tile_type.footprint();
+
+                                        if footprint != (1, 1) {
+                                            self.city.map.select_footprint(selection_end.clone(), footprint, blacklisted);
+                                        } else if self.filling {
+                                            self.city.map.select_area(selection_end.clone(), FILL_LIMIT, blacklisted);
+                                        } else if current_tile.tile_type.similar_to(&tile::Road) && self.routing {
+                                            self.city.map.select_route(selection_start.clone(), selection_end.clone(), blacklisted);
+                                        } else if current_tile.tile_type.similar_to(&tile::Road) {
+                                            self.city.map.select_line(selection_start.clone(), selection_end.clone(), blacklisted);
+                                        } else {
+                                            self.city.map.select(selection_start.clone(), selection_end.clone(), blacklisted);
+                                        }
+                                    }
                                 }
 
-                                let total_cost = current_tile.cost as f64 * self.city.map.num_selected as f64;
-                                self.selection_cost_text.set_entry_text(0, format!("${}", total_cost));
-                                if self.city.funds < total_cost {
+                                //plain rectangles are priced from their corners, without going through every tile's selection
+                                let plain_rectangle = !self.demolishing && !self.filling
+                                    && current_tile.tile_type.footprint() == (1, 1)
+                                    && !current_tile.tile_type.similar_to(&tile::Road);
+                                let total_cost = if plain_rectangle {
+                                    self.city.preview_cost(selection_start.clone(), selection_end.clone(), current_tile)
+                                } else {
+                                    self.city.selection_cost(current_tile)
+                                };
+                                self.selection_cost_text.set_entries(vec![(format!("${:.0}", total_cost), ())]);
+                                self.selection_affordable = self.city.funds >= total_cost;
+                                if !self.selection_affordable {
                                     self.selection_cost_text.highlight(Some(0));
                                 } else {
                                     self.selection_cost_text.highlight(None);
                                 }
 
-                                let pos = Vector2f::new(
-                                    if gui_pos.x + 16.0 > game.window.get_size().x as f32 - self.selection_cost_text.get_size().x {
-                                        gui_pos.x - self.selection_cost_text.get_size().x - 16.0
-                                    } else {
-                                        gui_pos.x + 16.0
-                                    },
-                                    if gui_pos.y - 16.0 > game.window.get_size().y as f32 - self.selection_cost_text.get_size().y {
-                                        gui_pos.y - self.selection_cost_text.get_size().y
-                                    } else {
-                                        gui_pos.y - 16.0
-                                    }
-                                );
-                                self.selection_cost_text.transform.set_position(&pos);
+                                self.selection_cost_text.place_near(&gui_pos, &game.window.get_size().to_vector2f(), 16.0);
                                 self.selection_cost_text.show();
                             },
                             None => {}
@@ -233,69 +1132,72 @@ impl<'s> game::GameState for EditState<'s> {
                     Panning(_) => {}
                     _ => {
                         self.action_state = Panning(Vector2f::new(x as f32, y as f32));
+                        self.view_target = None;
                         self.right_click_menu.hide();
+                        self.load_menu.hide();
                         self.selection_cost_text.hide();
                         self.info_text.hide();
                     },
                 },
                 MouseButtonPressed {button: mouse::MouseLeft, ..} => {
-                    if self.right_click_menu.visible() {
-                        match self.right_click_menu.activate_at(&gui_pos) {
-                            Some(&tile_name) if tile_name == "inspect" => self.current_tile = None,
-                            Some(tile_name) => self.current_tile = Some(game.tile_atlas.find_equiv(tile_name).expect("unknown tile").clone()),
-                            _ => {}
-                        }
-                        self.right_click_menu.hide();
-                    } else {
+                    if !self.click_popups(game, &gui_pos) {
                         match self.action_state {
                             Selecting(..) => {},
                             _ => {
-                                let (width, _) = self.city.map.size();
-                                let pos = Vector2i::new(
-                                    (game_pos.y / game.tile_size as f32 + game_pos.x / (2.0 * game.tile_size as f32) - width as f32 * 0.5 - 0.5) as i32,
-                                    (game_pos.y / game.tile_size as f32 - game_pos.x / (2.0 * game.tile_size as f32) + width as f32 * 0.5 + 0.5) as i32
-                                );
+                                let pos = self.city.map.screen_to_tile(&game_pos);
                                 match self.current_tile {
+                                    _ if self.setting_direction => match self.city.map.tile_at_mut(&pos) {
+                                        Some(&(ref mut tile, _, _)) => if tile.tile_type.similar_to(&tile::Road) {
+                                            tile.direction = tile::Direction::cycle(tile.direction.clone());
+                                        },
+                                        None => {}
+                                    },
+                                    _ if self.analyzing => {
+                                        let limits = self.city.growth_limits(&pos);
+                                        if limits.is_empty() {
+                                            self.info_text.set_entries(vec![("Nothing is holding it back".to_string(), ())]);
+                                        } else {
+                                            self.info_text.set_entries(limits.move_iter().map(|line| (line, ())).collect());
+                                        }
+
+                                        self.info_text.place_near(&gui_pos, &game.window.get_size().to_vector2f(), 16.0);
+                                        self.info_text.show();
+                                    },
                                     Some(_) => {
                                         self.action_state = Selecting(pos.clone(), pos);
                                     },
                                     None => {
                                         match self.city.map.tile_at(&pos) {
                                             Some(&(ref tile, resources, _)) => {
-                                                let mut entries = vec![(tile.tile_type.to_string(), ()), (format!("Resources: {}", resources), ())];
+                                                let mut entries: Vec<(String, ())> = tile.describe(resources).move_iter().map(|line| (line, ())).collect();
+
+                                                match self.city.map.elevation_at(&pos) {
+                                                    Some(elevation) => entries.push((format!("Elevation: {}", elevation), ())),
+                                                    None => {}
+                                                }
 
                                                 match tile.tile_type {
-                                                    tile::Residential {population, ..} => {
-                                                        entries.push((format!("Level: {}", tile.variant + 1), ()));
-                                                        entries.push((format!("Residents: {:.0}", population), ()));
-                                                    },
-                                                    tile::Commercial {population, ..} => {
-                                                        entries.push((format!("Level: {}", tile.variant + 1), ()));
-                                                        entries.push((format!("Employees: {:.0}", population), ()));
-                                                    },
-                                                    tile::Industrial {population, ..} => {
-                                                        entries.push((format!("Level: {}", tile.variant + 1), ()));
-                                                        entries.push((format!("Employees: {:.0}", population), ()));
+                                                    tile::Residential {..} => {
+                                                        let bonus = self.city.waterfront_bonus(&pos);
+                                                        if bonus > 0.0 {
+                                                            entries.push((format!("Waterfront +{:.0}%", bonus * 100.0), ()));
+                                                        }
                                                     },
                                                     _ => {}
                                                 }
 
-                                                self.info_text.set_entries(entries);
+                                                match self.city.map.region_summary(0, tile.regions[0]) {
+                                                    Some(summary) => entries.extend(summary.describe().move_iter().map(|line| (line, ()))),
+                                                    None => {}
+                                                }
 
-                                                let pos = Vector2f::new(
-                                                    if gui_pos.x + 16.0 > game.window.get_size().x as f32 - self.info_text.get_size().x {
-                                                        gui_pos.x - self.info_text.get_size().x - 16.0
-                                                    } else {
-                                                        gui_pos.x + 16.0
-                                                    },
-                                                    if gui_pos.y - 16.0 > game.window.get_size().y as f32 - self.info_text.get_size().y {
-                                                        gui_pos.y - self.info_text.get_size().y
-                                                    } else {
-                                                        gui_pos.y - 16.0
-                                                    }
-                                                );
+                                                match self.city.balance_report().warning() {
+                                                    Some(warning) => entries.push((warning, ())),
+                                                    None => {}
+                                                }
+                                                self.info_text.set_entries(entries);
 
-                                                self.info_text.transform.set_position(&pos);
+                                                self.info_text.place_near(&gui_pos, &game.window.get_size().to_vector2f(), 16.0);
 
                                                 self.info_text.show();
                                             },
@@ -315,22 +1217,14 @@ impl<'s> game::GameState for EditState<'s> {
                         self.city.map.clear_selected();
                         self.selection_cost_text.hide();
                     },
+                    //there's nothing to click in the menu while it can't be seen
+                    _ if self.hud_hidden => {},
                     _ => {
                         if !self.info_text.visible() {
-                            let pos = Vector2f::new(
-                                if gui_pos.x > game.window.get_size().x as f32 - self.right_click_menu.get_size().x {
-                                    gui_pos.x - self.right_click_menu.get_size().x
-                                } else {
-                                    gui_pos.x
-                                },
-                                if gui_pos.y > game.window.get_size().y as f32 - self.right_click_menu.get_size().y {
-                                    gui_pos.y - self.right_click_menu.get_size().y
-                                } else {
-                                    gui_pos.y
-                                }
-                            );
-
-                            self.right_click_menu.transform.set_position(&pos);
+                            self.right_click_menu.place_near(&gui_pos, &game.window.get_size().to_vector2f(), 0.0);
+                            //start from whatever is currently chosen
+                            let current_action = self.current_action;
+                            self.right_click_menu.highlight_message(&current_action);
                             self.right_click_menu.show();
                         } else {
                             self.info_text.hide();
@@ -342,11 +1236,31 @@ impl<'s> game::GameState for EditState<'s> {
                     Selecting(..) => {
                         match self.current_tile {
                             Some(ref current_tile) => {
-                                let total_cost = current_tile.cost as f64 * self.city.map.num_selected as f64;
-                                if self.city.funds >= total_cost {
-                                    self.city.bulldoze(current_tile);
-                                    self.city.funds -= total_cost;
-                                    self.city.tiles_changed();
+                                let total_cost = self.city.selection_cost(current_tile);
+                                match current_tile.tile_type.required_neighbor() {
+                                    Some(ref required) if !self.city.map.selection_adjacent_to(required) => {
+                                        self.info_text.set_entries(vec![(format!("Must be next to {}", required), ())]);
+                                        self.info_text.place_near(&gui_pos, &game.window.get_size().to_vector2f(), 16.0);
+                                        self.info_text.show();
+                                    },
+                                    _ => if self.city.funds >= total_cost {
+                                        let single_tile = if self.city.map.num_selected == 1 {
+                                            self.city.map.selected().next().map(|(index, _, _)| index)
+                                        } else {
+                                            None
+                                        };
+
+                                        self.city.bulldoze(current_tile);
+                                        self.city.funds -= total_cost;
+
+                                        match single_tile {
+                                            Some(index) => {
+                                                let (width, _) = self.city.map.size();
+                                                self.city.tile_changed(&Vector2i::new((index % width) as i32, (index / width) as i32));
+                                            },
+                                            None => self.city.tiles_changed()
+                                        }
+                                    }
                                 }
 
                                 self.action_state = Nothing;
@@ -358,17 +1272,123 @@ impl<'s> game::GameState for EditState<'s> {
                     },
                     _ => {}
                 },
-                MouseWheelMoved {delta, ..} if delta > 0 => {
-                    self.game_view.borrow_mut().zoom(2.0);
-                    self.zoom_level *= 2.0;
-                },
-                MouseWheelMoved {delta, ..} if delta < 0 => {
-                    self.game_view.borrow_mut().zoom(0.5);
-                    self.zoom_level *= 0.5;
-                },
+                MouseWheelMoved {delta, ..} if self.log_panel.visible() && self.log_panel.contains_point(&gui_pos) => self.scroll_log(delta),
+                MouseWheelMoved {delta, ..} if delta > 0 => self.zoom_at(&game.window, true, game.settings.pixel_zoom),
+                MouseWheelMoved {delta, ..} if delta < 0 => self.zoom_at(&game.window, false, game.settings.pixel_zoom),
                 NoEvent => break,
                 _ => {}
             }
         }
     }
-}
\ No newline at end of file
+
+    fn on_quit(&mut self, _game: &mut game::Game) {
+        self.save_records();
+    }
+}
+
+//the direction to scroll in when the cursor is close to the window border
+fn edge_scroll_direction(mouse_pos: &Vector2i, window_size: &Vector2f) -> Vector2f {
+    let width = window_size.x as i32;
+    let height = window_size.y as i32;
+
+    //the cursor is outside the window
+    if mouse_pos.x < 0 || mouse_pos.y < 0 || mouse_pos.x >= width || mouse_pos.y >= height {
+        return Vector2f::new(0.0, 0.0);
+    }
+
+    let x = if mouse_pos.x < EDGE_SCROLL_MARGIN {
+        -1.0
+    } else if mouse_pos.x >= width - EDGE_SCROLL_MARGIN {
+        1.0
+    } else {
+        0.0
+    };
+
+    let y = if mouse_pos.y < EDGE_SCROLL_MARGIN {
+        -1.0
+    } else if mouse_pos.y >= height - EDGE_SCROLL_MARGIN {
+        1.0
+    } else {
+        0.0
+    };
+
+    Vector2f::new(x, y)
+}
+
+//the zoom level is the number of world units per pixel, so zooming out makes it larger
+fn next_zoom(zoom_level: f32, zoom_out: bool, pixel_zoom: bool) -> f32 {
+    if !pixel_zoom {
+        let zoom_level = if zoom_out { zoom_level * ZOOM_STEP } else { zoom_level / ZOOM_STEP };
+        return zoom_level.max(MIN_ZOOM).min(MAX_ZOOM);
+    }
+
+    //step between whole pixel scales, like 3x, 2x, 1x, 1/2x and 1/3x
+    let zoom_level = snap_zoom(zoom_level);
+    let zoom_level = if zoom_level <= 1.0 {
+        let scale = (1.0 / zoom_level).round() + if zoom_out { -1.0 } else { 1.0 };
+        if scale >= 1.0 { 1.0 / scale } else { 2.0 }
+    } else {
+        let shrink = zoom_level.round() + if zoom_out { 1.0 } else { -1.0 };
+        shrink.max(1.0)
+    };
+
+    zoom_level.max(MIN_ZOOM).min(MAX_ZOOM)
+}
+
+//the closest zoom level where each texel covers a whole number of pixels, or the other way around
+fn snap_zoom(zoom_level: f32) -> f32 {
+    if zoom_level <= 1.0 {
+        1.0 / (1.0 / zoom_level).round()
+    } else {
+        zoom_level.round()
+    }
+}
+
+//the range of log lines to show, when the newest scrolled lines are left out
+fn log_window(log_length: uint, scroll: uint, shown: uint) -> (uint, uint) {
+    let scroll = min(scroll, log_length.saturating_sub(shown));
+    let end = log_length - scroll;
+    (end.saturating_sub(shown), end)
+}
+
+//the number keys select bookmark slots, with 0 as the last one
+fn bookmark_slot(key: Key) -> Option<uint> {
+    let slot = match key {
+        keyboard::Num1 => 0,
+        keyboard::Num2 => 1,
+        keyboard::Num3 => 2,
+        keyboard::Num4 => 3,
+        keyboard::Num5 => 4,
+        keyboard::Num6 => 5,
+        keyboard::Num7 => 6,
+        keyboard::Num8 => 7,
+        keyboard::Num9 => 8,
+        keyboard::Num0 => 9,
+        _ => return None
+    };
+
+    Some(slot)
+}
+
+#[cfg(test)]
+mod test {
+    use super::log_window;
+
+    #[test]
+    fn the_newest_lines_are_shown_by_default() {
+        assert_eq!(log_window(25, 0, 10), (15, 25));
+        assert_eq!(log_window(4, 0, 10), (0, 4));
+    }
+
+    #[test]
+    fn scrolling_shows_older_lines() {
+        assert_eq!(log_window(25, 3, 10), (12, 22));
+        assert_eq!(log_window(25, 15, 10), (0, 10));
+    }
+
+    #[test]
+    fn scrolling_stops_at_the_oldest_line() {
+        assert_eq!(log_window(25, 100, 10), (0, 10));
+        assert_eq!(log_window(4, 2, 10), (0, 4));
+    }
+}
@@ -4,20 +4,35 @@ use std::collections::HashMap;
 
 use rsfml;
 use rsfml::window::VideoMode;
-use rsfml::graphics::{RenderWindow, Color, Font};
+use rsfml::graphics::{RenderWindow, Color, Font, IntRect};
+use rsfml::system::vector2::Vector2f;
 use rsfml::graphics::rc::Sprite;
 
 use gui;
+use saves;
+use key_bindings::{KeyBindings, KEY_BINDINGS_PATH};
+use settings::{Settings, SETTINGS_PATH};
 
 use tile;
 use tile::{Tile, TileType};
 
+pub static DEFAULT_TILE_SIZE: uint = 8;
+
+pub static CURSOR_POINTER: uint = 0;
+pub static CURSOR_BUILD: uint = 1;
+pub static CURSOR_PAN: uint = 2;
+pub static CURSOR_BLOCKED: uint = 3;
+static CURSOR_SIZE: i32 = 16;
+
+pub static THEMES: [&'static str, ..3] = ["classic", "dark", "high_contrast"];
+
 pub type TextureRc = Rc<RefCell<rsfml::graphics::Texture>>;
 
 pub trait GameState {
     fn draw(&mut self, dt: f32, game: &mut Game);
     fn update(&mut self, dt: f32);
     fn handle_input(&mut self, game: &mut Game);
+    fn on_quit(&mut self, _game: &mut Game) {}
 }
 
 impl<'a> GameState for Rc<RefCell<Box<GameState + 'a>>> {
@@ -32,13 +47,24 @@ impl<'a> GameState for Rc<RefCell<Box<GameState + 'a>>> {
     fn handle_input(&mut self, game: &mut Game) {
         self.borrow_mut().handle_input(game)
     }
+
+    fn on_quit(&mut self, game: &mut Game) {
+        self.borrow_mut().on_quit(game)
+    }
 }
 
 pub struct Game<'a> {
     states: Vec<Rc<RefCell<Box<GameState + 'a>>>>,
     textures: TextureManager,
+    quit_requested: bool,
     pub tile_size: uint,
+    pub settings: Settings,
+    pub theme: uint,
+    pub key_bindings: KeyBindings,
     pub background: Sprite,
+    cursor: Sprite,
+    cursor_drawn: bool,
+    cursor_hidden: bool,
     pub window: RenderWindow,
     pub tile_atlas: HashMap<&'static str, Tile>,
     pub fonts: HashMap<&'static str, Rc<RefCell<Font>>>,
@@ -46,19 +72,21 @@ pub struct Game<'a> {
 }
 
 impl<'a> Game<'a> {
-    pub fn new() -> Option<Game<'a>> {
+    pub fn new(tile_size: uint) -> Option<Game<'a>> {
+        let settings = Settings::load(&Path::new(SETTINGS_PATH)).unwrap_or_else(|_| Settings::new());
+        let theme = THEMES.iter().position(|&name| name == settings.theme.as_slice()).unwrap_or(0);
+
         let maybe_window = RenderWindow::new(
-            VideoMode::new_init(800, 600, 32),
+            VideoMode::new_init(settings.window_width, settings.window_height, 32),
             "Super Mega City Builder",
             rsfml::window::DefaultStyle,
             &rsfml::window::ContextSettings::default()
         );
 
-        let tile_size = 8;
-
         maybe_window.map(|mut window| {
             let texture_manager = load_textures();
             let background = texture_manager.get_ref("background").expect("background texture was not loaded");
+            let cursor = texture_manager.get_ref("cursors").expect("cursor texture was not loaded");
             let tiles = load_tiles(&texture_manager, tile_size);
             let fonts = load_fonts();
             window.set_framerate_limit(60);
@@ -66,11 +94,18 @@ impl<'a> Game<'a> {
             Game {
                 states: Vec::new(),
                 textures: texture_manager,
+                quit_requested: false,
                 tile_size: tile_size,
+                settings: settings,
+                theme: theme,
+                key_bindings: KeyBindings::load(&Path::new(KEY_BINDINGS_PATH)).unwrap_or_else(|_| KeyBindings::new()),
                 background: Sprite::new_with_texture(background).expect("could not create background sprite"),
+                cursor: Sprite::new_with_texture(cursor).expect("could not create cursor sprite"),
+                cursor_drawn: false,
+                cursor_hidden: false,
                 window: window,
                 tile_atlas: tiles,
-                stylesheets: make_stylesheets(&fonts),
+                stylesheets: make_stylesheets(&fonts, THEMES[theme]),
                 fonts: fonts
             }
         })
@@ -93,10 +128,41 @@ impl<'a> Game<'a> {
         self.states.last().map(|state| state.clone())
     }
 
-    pub fn game_loop(&mut self) {
+    //switches to the next theme and rebuilds the stylesheets, which the states have to pass on to their guis
+    pub fn cycle_theme(&mut self) -> Result<(), String> {
+        self.theme = (self.theme + 1) % THEMES.len();
+        self.stylesheets = make_stylesheets(&self.fonts, THEMES[self.theme]);
+        self.settings.theme = THEMES[self.theme].to_string();
+        self.save_settings()
+    }
+
+    //the error is left to the states, which can show it
+    pub fn save_settings(&self) -> Result<(), String> {
+        self.settings.save(&Path::new(SETTINGS_PATH)).map_err(|e| format!("Could not save settings: {}", saves::describe_error(&e)))
+    }
+
+    //draws one of the software cursors in the current view, in place of the system cursor
+    pub fn draw_cursor(&mut self, cursor: uint, position: &Vector2f) {
+        self.cursor.set_texture_rect(&IntRect::new(cursor as i32 * CURSOR_SIZE, 0, CURSOR_SIZE, CURSOR_SIZE));
+
+        //the pointer points with its tip, and the others are centered
+        let origin = if cursor == CURSOR_POINTER { 0.0 } else { CURSOR_SIZE as f32 / 2.0 };
+        self.cursor.set_origin(&Vector2f::new(origin, origin));
+        self.cursor.set_position(position);
+
+        self.window.draw(&self.cursor);
+        self.cursor_drawn = true;
+    }
+
+    pub fn request_quit(&mut self) {
+        self.quit_requested = true;
+    }
+
+    //runs until the window is closed, and returns the error if the settings couldn't be saved at the end
+    pub fn game_loop(&mut self) -> Result<(), String> {
         let mut clock = rsfml::system::Clock::new();
 
-        while self.window.is_open() {
+        while self.window.is_open() && !self.quit_requested {
             let elapsed = clock.restart();
             let dt = elapsed.as_seconds();
 
@@ -106,18 +172,56 @@ impl<'a> Game<'a> {
                     state.update(dt);
                     
                     self.window.clear(&rsfml::graphics::Color::black());
+                    self.cursor_drawn = false;
                     state.draw(dt, self);
                     self.window.display();
+
+                    //states that don't draw a cursor of their own get the system cursor back
+                    if self.cursor_drawn != self.cursor_hidden {
+                        self.window.set_mouse_cursor_visible(!self.cursor_drawn);
+                        self.cursor_hidden = self.cursor_drawn;
+                    }
                 },
                 None => {}
             }
         }
+
+        self.teardown()
+    }
+
+    fn teardown(&mut self) -> Result<(), String> {
+        //let every state, from the top, clean up before the window goes away
+        loop {
+            match self.peek_state() {
+                Some(mut state) => {
+                    state.on_quit(self);
+                    self.pop_state();
+                },
+                None => break
+            }
+        }
+
+        if self.window.is_open() {
+            let size = self.window.get_size();
+            self.settings.window_width = size.x as uint;
+            self.settings.window_height = size.y as uint;
+            let saved = self.save_settings();
+
+            self.window.close();
+            return saved;
+        }
+
+        Ok(())
     }
 }
 
 fn load_textures() -> TextureManager {
     let mut manager = TextureManager { textures: HashMap::new() };
 
+    if !manager.load_texture("void", "media/void.png") {
+        fail!("could not load texture: media/void.png");
+    }
+
     if !manager.load_texture("grass", "media/grass.png") {
         fail!("could not load texture: media/grass.png");
     }
@@ -146,6 +250,10 @@ fn load_textures() -> TextureManager {
         fail!("could not load texture: media/road.png");
     }
 
+    if !manager.load_texture("cursors", "media/cursors.png") {
+        fail!("could not load texture: media/cursors.png");
+    }
+
     if !manager.load_texture("background", "media/background.png") {
         fail!("could not load texture: media/background.png");
     }
@@ -156,6 +264,14 @@ fn load_textures() -> TextureManager {
 fn load_tiles(textures: &TextureManager, tile_size: uint) -> HashMap<&'static str, Tile> {
     let mut tiles = HashMap::new();
 
+    //cliffs and chasms that nothing can be built on
+    tiles.insert("void", Tile::new(
+        tile_size, 1,
+        textures.get_ref("void").expect("void texture not loaded"),
+        vec![tile::Animation::new_static()],
+        tile::Void, 0
+    ));
+
     tiles.insert("grass", Tile::new(
         tile_size, 1,
         textures.get_ref("grass").expect("grass texture not loaded"),
@@ -163,6 +279,13 @@ fn load_tiles(textures: &TextureManager, tile_size: uint) -> HashMap<&'static st
         tile::Grass, 50
     ));
 
+    tiles.insert("demolish", Tile::new(
+        tile_size, 1,
+        textures.get_ref("grass").expect("grass texture not loaded"),
+        vec![tile::Animation::new_static()],
+        tile::Grass, 10
+    ));
+
     tiles.insert("forest", Tile::new(
         tile_size, 1,
         textures.get_ref("forest").expect("forest texture not loaded"),
@@ -177,11 +300,25 @@ fn load_tiles(textures: &TextureManager, tile_size: uint) -> HashMap<&'static st
         tile::Water, 0
     ));
 
+    tiles.insert("residential_low", Tile::new(
+        tile_size, 2,
+        textures.get_ref("residential").expect("residential texture not loaded"),
+        Vec::from_elem(6, tile::Animation::new_static()),
+        TileType::residential(tile::LowDensity, 20, 2), 200
+    ));
+
     tiles.insert("residential", Tile::new(
         tile_size, 2,
         textures.get_ref("residential").expect("residential texture not loaded"),
         Vec::from_elem(6, tile::Animation::new_static()),
-        TileType::residential(50, 6), 300
+        TileType::residential(tile::MediumDensity, 50, 6), 300
+    ));
+
+    tiles.insert("residential_high", Tile::new(
+        tile_size, 2,
+        textures.get_ref("residential").expect("residential texture not loaded"),
+        Vec::from_elem(6, tile::Animation::new_static()),
+        TileType::residential(tile::HighDensity, 80, 6), 500
     ));
 
     tiles.insert("commercial", Tile::new(
@@ -205,6 +342,21 @@ fn load_tiles(textures: &TextureManager, tile_size: uint) -> HashMap<&'static st
         tile::Road, 100
     ));
 
+    //there is no airport sprite yet, so it borrows the industrial one
+    tiles.insert("airport", Tile::new(
+        tile_size, 2,
+        textures.get_ref("industrial").expect("industrial texture not loaded"),
+        vec![tile::Animation::new_static()],
+        tile::Airport, 5000
+    ));
+
+    //a tile size that doesn't match the art would draw parts of the neighboring frames
+    for (name, tile) in tiles.iter() {
+        if !tile.fits_texture() {
+            fail!("the {} texture is too small for a tile size of {}", name, tile_size);
+        }
+    }
+
     tiles
 }
 
@@ -212,39 +364,78 @@ pub fn load_fonts() -> HashMap<&'static str, Rc<RefCell<Font>>> {
     let mut fonts = HashMap::new();
 
     fonts.insert("main_font", Rc::new(RefCell::new(Font::new_from_file("media/font.ttf").expect("could not load main font"))));
+    fonts.insert("text_font", Rc::new(RefCell::new(Font::new_from_file("media/text_font.ttf").expect("could not load text font"))));
+
+    //the fallback font is optional and only used for glyphs the main font lacks
+    match Font::new_from_file("media/fallback_font.ttf") {
+        Some(font) => { fonts.insert("fallback_font", Rc::new(RefCell::new(font))); },
+        None => {}
+    }
 
     fonts
 }
 
-pub fn make_stylesheets(fonts: &HashMap<&'static str, Rc<RefCell<Font>>>) -> HashMap<&'static str, gui::GuiStyle> {
+pub fn make_stylesheets(fonts: &HashMap<&'static str, Rc<RefCell<Font>>>, theme: &str) -> HashMap<&'static str, gui::GuiStyle> {
     let mut stylesheets = HashMap::new();
     let font = fonts.find(&"main_font").expect("main font not loaded").clone();
-
-    stylesheets.insert("button", gui::GuiStyle {
-        font: font.clone(),
-        border_size: 1.0,
-        body_color: Color::new_RGB(0xc6, 0xc6, 0xc6),
-        border_color: Color::new_RGB(0x94, 0x94, 0x94),
-        text_color: Color::new_RGB(0x00, 0x00, 0x00),
-        body_highlight_color: Color::new_RGB(0x61, 0x61, 0x61),
-        border_highlight_color: Color::new_RGB(0x94, 0x94, 0x94),
-        text_highlight_color: Color::new_RGB(0x00, 0x00, 0x00)
-    });
-
-    stylesheets.insert("text", gui::GuiStyle {
-        font: font,
-        border_size: 0.0,
-        body_color: Color::new_RGBA(0x00, 0x00, 0x00, 0x00),
-        border_color: Color::new_RGB(0x00, 0x00, 0x00),
-        text_color: Color::new_RGB(0xff, 0xff, 0xff),
-        body_highlight_color: Color::new_RGBA(0x00, 0x00, 0x00, 0x00),
-        border_highlight_color: Color::new_RGB(0x00, 0x00, 0x00),
-        text_highlight_color: Color::new_RGB(0xff, 0x00, 0x00)
-    });
+    //the loose text, like the selection cost, is set apart from the buttons
+    let text_font = fonts.find(&"text_font").expect("text font not loaded").clone();
+    let fallback_font = fonts.find(&"fallback_font").map(|font| font.clone());
+
+    //the colors are body, border and text, followed by their highlighted versions
+    let (button, text) = match theme {
+        "dark" => (
+            make_style(&font, &fallback_font, 1.0, [
+                Color::new_RGB(0x30, 0x30, 0x30), Color::new_RGB(0x50, 0x50, 0x50), Color::new_RGB(0xe0, 0xe0, 0xe0),
+                Color::new_RGB(0x60, 0x60, 0x60), Color::new_RGB(0x80, 0x80, 0x80), Color::new_RGB(0xff, 0xff, 0xff)
+            ]),
+            make_style(&text_font, &fallback_font, 0.0, [
+                Color::new_RGBA(0x00, 0x00, 0x00, 0x00), Color::new_RGB(0x00, 0x00, 0x00), Color::new_RGB(0xe0, 0xe0, 0xe0),
+                Color::new_RGBA(0x00, 0x00, 0x00, 0x00), Color::new_RGB(0x00, 0x00, 0x00), Color::new_RGB(0xff, 0x80, 0x40)
+            ])
+        ),
+        "high_contrast" => (
+            make_style(&font, &fallback_font, 2.0, [
+                Color::new_RGB(0x00, 0x00, 0x00), Color::new_RGB(0xff, 0xff, 0xff), Color::new_RGB(0xff, 0xff, 0xff),
+                Color::new_RGB(0xff, 0xff, 0x00), Color::new_RGB(0xff, 0xff, 0xff), Color::new_RGB(0x00, 0x00, 0x00)
+            ]),
+            make_style(&text_font, &fallback_font, 0.0, [
+                Color::new_RGBA(0x00, 0x00, 0x00, 0x00), Color::new_RGB(0x00, 0x00, 0x00), Color::new_RGB(0xff, 0xff, 0xff),
+                Color::new_RGBA(0x00, 0x00, 0x00, 0x00), Color::new_RGB(0x00, 0x00, 0x00), Color::new_RGB(0xff, 0xff, 0x00)
+            ])
+        ),
+        _ => (
+            make_style(&font, &fallback_font, 1.0, [
+                Color::new_RGB(0xc6, 0xc6, 0xc6), Color::new_RGB(0x94, 0x94, 0x94), Color::new_RGB(0x00, 0x00, 0x00),
+                Color::new_RGB(0x61, 0x61, 0x61), Color::new_RGB(0x94, 0x94, 0x94), Color::new_RGB(0x00, 0x00, 0x00)
+            ]),
+            make_style(&text_font, &fallback_font, 0.0, [
+                Color::new_RGBA(0x00, 0x00, 0x00, 0x00), Color::new_RGB(0x00, 0x00, 0x00), Color::new_RGB(0xff, 0xff, 0xff),
+                Color::new_RGBA(0x00, 0x00, 0x00, 0x00), Color::new_RGB(0x00, 0x00, 0x00), Color::new_RGB(0xff, 0x00, 0x00)
+            ])
+        )
+    };
+
+    stylesheets.insert("button", button);
+    stylesheets.insert("text", text);
 
     stylesheets
 }
 
+fn make_style(font: &Rc<RefCell<Font>>, fallback_font: &Option<Rc<RefCell<Font>>>, border_size: f32, colors: [Color, ..6]) -> gui::GuiStyle {
+    gui::GuiStyle {
+        font: font.clone(),
+        fallback_font: fallback_font.clone(),
+        border_size: border_size,
+        body_color: colors[0],
+        border_color: colors[1],
+        text_color: colors[2],
+        body_highlight_color: colors[3],
+        border_highlight_color: colors[4],
+        text_highlight_color: colors[5]
+    }
+}
+
 pub struct TextureManager {
     textures: HashMap<&'static str, TextureRc>
 }
@@ -5,6 +5,8 @@ use std::str::StrAllocating;
 use rsfml::graphics::{Color, Font, RectangleShape, Transformable, RenderWindow, RenderTexture};
 use rsfml::graphics::rc::{Text};
 use rsfml::system::vector2::Vector2f;
+use rsfml::window::keyboard;
+use rsfml::window::keyboard::Key;
 use rsfml::traits::Drawable;
 
 #[deriving(Clone)]
@@ -16,13 +18,162 @@ pub struct GuiStyle {
     pub text_color: Color,
     pub text_highlight_color: Color,
     pub font: Rc<RefCell<Font>>,
+    pub fallback_font: Option<Rc<RefCell<Font>>>,
     pub border_size: f32
 }
 
+impl GuiStyle {
+    pub fn font_for(&self, text: &str, character_size: uint) -> Rc<RefCell<Font>> {
+        match self.fallback_font {
+            Some(ref fallback) if !has_glyphs(self.font.borrow().deref(), text, character_size) => fallback.clone(),
+            _ => self.font.clone()
+        }
+    }
+}
+
+fn has_glyphs(font: &Font, text: &str, character_size: uint) -> bool {
+    //missing glyphs may still have an advance, but there's nothing to draw
+    text.chars().all(|c| c.is_whitespace() || c.is_control() || {
+        let bounds = font.get_glyph(c as u32, character_size, false).bounds;
+        bounds.width > 0 && bounds.height > 0
+    })
+}
+
+//vertical bars that grow up from a baseline for positive values and down for negative ones
+pub struct BarChart<'s> {
+    bars: Vec<RectangleShape<'s>>,
+    position: Vector2f,
+    bar_width: f32,
+    max_height: f32
+}
+
+impl<'s> BarChart<'s> {
+    pub fn new(colors: &[Color], bar_width: f32, max_height: f32) -> BarChart<'s> {
+        BarChart {
+            bars: colors.iter().map(|color| {
+                let mut bar = RectangleShape::new().expect("unable to create new rectangle shape");
+                bar.set_fill_color(color);
+                bar
+            }).collect(),
+            position: Vector2f::new(0.0, 0.0),
+            bar_width: bar_width,
+            max_height: max_height
+        }
+    }
+
+    //the left end of the baseline
+    pub fn set_position(&mut self, position: &Vector2f) {
+        self.position = *position;
+    }
+
+    //the values are clamped between -1 and 1
+    pub fn set_values(&mut self, values: &[f64]) {
+        for (index, (bar, &value)) in self.bars.mut_iter().zip(values.iter()).enumerate() {
+            let height = value.max(-1.0).min(1.0) as f32 * self.max_height;
+            let top = if height > 0.0 { self.position.y - height } else { self.position.y };
+
+            bar.set_size(&Vector2f::new(self.bar_width, height.abs()));
+            bar.set_position(&Vector2f::new(self.position.x + index as f32 * (self.bar_width + 2.0), top));
+        }
+    }
+}
+
+impl<'s> Drawable for BarChart<'s> {
+    fn draw_in_render_window(&self, render_window: &mut RenderWindow) {
+        for bar in self.bars.iter() {
+            render_window.draw(bar);
+        }
+    }
+
+    fn draw_in_render_texture(&self, render_texture: &mut RenderTexture) {
+        for bar in self.bars.iter() {
+            render_texture.draw(bar);
+        }
+    }
+}
+
+//short messages stacked above a corner, each one removed when its time runs out
+pub struct Notifications<'s> {
+    messages: Vec<(String, f32)>,
+    max_messages: uint,
+    corner: Vector2f,
+    panel: Gui<'s, 'static, ()>
+}
+
+impl<'s> Notifications<'s> {
+    pub fn new(width: f32, max_messages: uint, style: GuiStyle) -> Notifications<'s> {
+        Notifications {
+            messages: Vec::new(),
+            max_messages: max_messages,
+            corner: Vector2f::new(0.0, 0.0),
+            panel: Gui::empty(Vector2f::new(width, 16.0), 2, false, style)
+        }
+    }
+
+    //the oldest message is pushed out when there are too many
+    pub fn push(&mut self, message: String, duration: f32) {
+        self.messages.push((message, duration));
+
+        while self.messages.len() > self.max_messages {
+            self.messages.remove(0);
+        }
+
+        self.refresh();
+    }
+
+    pub fn update(&mut self, dt: f32) {
+        let count = self.messages.len();
+
+        for &(_, ref mut remaining) in self.messages.mut_iter() {
+            *remaining -= dt;
+        }
+        self.messages.retain(|&(_, remaining)| remaining > 0.0);
+
+        if self.messages.len() != count {
+            self.refresh();
+        }
+    }
+
+    //the bottom right corner of the stack
+    pub fn set_corner(&mut self, corner: &Vector2f) {
+        self.corner = *corner;
+        self.refresh();
+    }
+
+    pub fn set_style(&mut self, style: GuiStyle) {
+        self.panel.set_style(style);
+    }
+
+    fn refresh(&mut self) {
+        let entries: Vec<(String, ())> = self.messages.iter().map(|&(ref message, _)| (message.clone(), ())).collect();
+        self.panel.set_entries(entries);
+
+        let size = self.panel.measure();
+        self.panel.transform.set_position(&self.corner.sub(&size));
+
+        if self.messages.is_empty() {
+            self.panel.hide();
+        } else {
+            self.panel.show();
+        }
+    }
+}
+
+impl<'s> Drawable for Notifications<'s> {
+    fn draw_in_render_window(&self, render_window: &mut RenderWindow) {
+        render_window.draw(&self.panel);
+    }
+
+    fn draw_in_render_texture(&self, render_texture: &mut RenderTexture) {
+        render_texture.draw(&self.panel);
+    }
+}
+
 pub struct GuiEntry<'s, 't, T: 't> {
     pub shape: RectangleShape<'s>,
     pub message: T,
-    pub text: Text
+    pub text: Text,
+    pub enabled: bool
 }
 
 pub struct Gui<'s, 't, T: 't> {
@@ -31,6 +182,7 @@ pub struct Gui<'s, 't, T: 't> {
     dimensions: Vector2f,
     padding: i32,
     visible: bool,
+    focused: Option<uint>,
     rect: RectangleShape<'s>,
     pub transform: Transformable,
     pub entries: Vec<GuiEntry<'s, 't, T>>
@@ -49,14 +201,17 @@ impl<'s, 't, T: 't> Gui<'s, 't, T> {
             dimensions: dimensions,
             padding: padding,
             visible: false,
+            focused: None,
             transform: Transformable::new().unwrap(),
             entries: entries.move_iter().map(|(text_str, message)| {
-                let mut text = Text::new_init(text_str.as_slice(), style.font.clone(), (dimensions.y - style.border_size - padding as f32) as uint).unwrap();
+                let character_size = (dimensions.y - style.border_size - padding as f32) as uint;
+                let mut text = Text::new_init(text_str.as_slice(), style.font_for(text_str.as_slice(), character_size), character_size).unwrap();
                 text.set_color(&style.text_color);
                 GuiEntry {
                     shape: rect.clone(),
                     message: message,
-                    text:text
+                    text:text,
+                    enabled: true
                 }
             }).collect(),
             rect: rect,
@@ -64,8 +219,42 @@ impl<'s, 't, T: 't> Gui<'s, 't, T> {
         }
     }
 
-    pub fn get_size(&self) -> Vector2f {
-        Vector2f::new(self.dimensions.x, self.dimensions.y * self.entries.len() as f32)
+    //for panels that get their entries later, through set_entries
+    pub fn empty(dimensions: Vector2f, padding: i32, horizontal: bool, style: GuiStyle) -> Gui<'s, 't, T> {
+        Gui::new::<&'static str>(dimensions, padding, horizontal, style, Vec::new())
+    }
+
+    pub fn entry_dimensions(&self) -> Vector2f {
+        self.dimensions.clone()
+    }
+
+    pub fn measure(&self) -> Vector2f {
+        let count = self.entries.len() as f32;
+
+        if self.horizontal {
+            Vector2f::new(self.dimensions.x * count, self.dimensions.y)
+        } else {
+            Vector2f::new(self.dimensions.x, self.dimensions.y * count)
+        }
+    }
+
+    pub fn place_near(&mut self, anchor: &Vector2f, window_size: &Vector2f, margin: f32) {
+        let size = self.measure();
+
+        //flip to the other side of the anchor if it would end up outside the window
+        let x = if anchor.x + margin + size.x > window_size.x {
+            anchor.x - margin - size.x
+        } else {
+            anchor.x + margin
+        };
+
+        let y = if anchor.y - margin + size.y > window_size.y {
+            anchor.y + margin - size.y
+        } else {
+            anchor.y - margin
+        };
+
+        self.transform.set_position(&Vector2f::new(x, y));
     }
 
     pub fn get_entry(&self, mouse_pos: &Vector2f) -> Option<uint> {
@@ -88,24 +277,63 @@ impl<'s, 't, T: 't> Gui<'s, 't, T> {
         None
     }
 
+    //true if the point is over any part of the gui while it's visible
+    pub fn contains_point(&self, point: &Vector2f) -> bool {
+        if !self.visible {
+            return false;
+        }
+
+        let top_left = self.transform.get_position().sub(&self.transform.get_origin());
+        let size = self.measure();
+
+        point.x >= top_left.x && point.x <= top_left.x + size.x && point.y >= top_left.y && point.y <= top_left.y + size.y
+    }
+
+    //restyles the gui and all of its entries
+    pub fn set_style(&mut self, style: GuiStyle) {
+        let character_size = (self.dimensions.y - style.border_size - self.padding as f32) as uint;
+
+        self.rect.set_fill_color(&style.body_color);
+        self.rect.set_outline_thickness(style.border_size);
+        self.rect.set_outline_color(&style.border_color);
+
+        for entry in self.entries.mut_iter() {
+            entry.shape.set_outline_thickness(style.border_size);
+            let font = style.font_for(entry.text.get_string().as_slice(), character_size);
+            entry.text.set_font(font);
+            entry.text.set_character_size(character_size);
+        }
+
+        self.style = style;
+
+        let focused = self.focused;
+        self.highlight(focused);
+    }
+
     pub fn visible(&self) -> bool {
         self.visible
     }
 
     pub fn set_entry_text<Txt: StrAllocating>(&mut self, entry: uint, text: Txt) {
         if entry < self.entries.len() {
-            self.entries.get_mut(entry).text.set_string(text.as_slice());
+            let character_size = (self.dimensions.y - self.style.border_size - self.padding as f32) as uint;
+            let entry = self.entries.get_mut(entry);
+            entry.text.set_font(self.style.font_for(text.as_slice(), character_size));
+            entry.text.set_string(text.as_slice());
         }
     }
 
     pub fn set_entries<Txt: StrAllocating>(&mut self, entries: Vec<(Txt, T)>) {
+        self.focused = None;
         self.entries = entries.move_iter().map(|(text_str, message)| {
-            let mut text = Text::new_init(text_str.as_slice(), self.style.font.clone(), (self.dimensions.y - self.style.border_size - self.padding as f32) as uint).unwrap();
+            let character_size = (self.dimensions.y - self.style.border_size - self.padding as f32) as uint;
+            let mut text = Text::new_init(text_str.as_slice(), self.style.font_for(text_str.as_slice(), character_size), character_size).unwrap();
             text.set_color(&self.style.text_color);
             GuiEntry {
                 shape: self.rect.clone(),
                 message: message,
-                text:text
+                text:text,
+                enabled: true
             }
         }).collect()
     }
@@ -120,6 +348,17 @@ impl<'s, 't, T: 't> Gui<'s, 't, T> {
         self.dimensions = dimensions.clone();
     }
 
+    //stretches the entries so that the whole gui measures width across
+    pub fn fit_width(&mut self, width: f32) {
+        let measured = self.measure().x;
+        if measured <= 0.0 {
+            return;
+        }
+
+        let dimensions = Vector2f::new(self.dimensions.x * width / measured, self.dimensions.y);
+        self.set_dimensions(&dimensions);
+    }
+
     pub fn show(&mut self) {
         self.visible = true;
 
@@ -147,7 +386,12 @@ impl<'s, 't, T: 't> Gui<'s, 't, T> {
     pub fn highlight(&mut self, index: Option<uint>) {
         let index = index.unwrap_or(self.entries.len());
         for (i, entry) in self.entries.mut_iter().enumerate() {
-            if i == index {
+            if !entry.enabled {
+                let text = self.style.text_color;
+                entry.shape.set_fill_color(&self.style.body_color);
+                entry.shape.set_outline_color(&self.style.border_color);
+                entry.text.set_color(&Color::new_RGBA(text.red, text.green, text.blue, text.alpha / 3));
+            } else if i == index {
                 entry.shape.set_fill_color(&self.style.body_highlight_color);
                 entry.shape.set_outline_color(&self.style.border_highlight_color);
                 entry.text.set_color(&self.style.text_highlight_color);
@@ -159,8 +403,65 @@ impl<'s, 't, T: 't> Gui<'s, 't, T> {
         }
     }
 
+    pub fn focused(&self) -> Option<uint> {
+        self.focused
+    }
+
+    pub fn focus(&mut self, index: Option<uint>) {
+        self.focused = match index {
+            Some(index) if index < self.entries.len() => Some(index),
+            _ => None
+        };
+        let focused = self.focused;
+        self.highlight(focused);
+    }
+
+    pub fn focus_next(&mut self) {
+        if self.entries.len() > 0 {
+            let next = self.focused.map_or(0, |index| (index + 1) % self.entries.len());
+            self.focus(Some(next));
+        }
+    }
+
+    pub fn focus_previous(&mut self) {
+        if self.entries.len() > 0 {
+            let previous = self.focused.map_or(self.entries.len() - 1, |index| (index + self.entries.len() - 1) % self.entries.len());
+            self.focus(Some(previous));
+        }
+    }
+
+    //moves the focus with the arrow keys and activates the focused entry on Return
+    pub fn handle_key(&mut self, key: Key) -> Option<&T> {
+        if !self.visible {
+            return None;
+        }
+
+        match key {
+            keyboard::Down if !self.horizontal => self.focus_next(),
+            keyboard::Up if !self.horizontal => self.focus_previous(),
+            keyboard::Right if self.horizontal => self.focus_next(),
+            keyboard::Left if self.horizontal => self.focus_previous(),
+            keyboard::Return => return match self.focused {
+                Some(index) => self.activate(index),
+                None => None
+            },
+            _ => {}
+        }
+
+        None
+    }
+
+    //disabled entries are greyed out and can't be activated
+    pub fn set_entry_enabled(&mut self, index: uint, enabled: bool) {
+        if index < self.entries.len() && self.entries[index].enabled != enabled {
+            self.entries.get_mut(index).enabled = enabled;
+            let focused = self.focused;
+            self.highlight(focused);
+        }
+    }
+
     pub fn activate(&self, index: uint) -> Option<&T> {
-        if index >= self.entries.len() {
+        if index >= self.entries.len() || !self.entries[index].enabled {
             return None;
         }
 
@@ -172,6 +473,15 @@ impl<'s, 't, T: 't> Gui<'s, 't, T> {
     }
 }
 
+impl<'s, 't, T: PartialEq + 't> Gui<'s, 't, T> {
+    //focuses the first entry with the message, or nothing if there is none, and returns true if it was found
+    pub fn highlight_message(&mut self, message: &T) -> bool {
+        let index = self.entries.iter().position(|entry| entry.message == *message);
+        self.focus(index);
+        index.is_some()
+    }
+}
+
 impl<'s, 't, T: 't> Drawable for Gui<'s, 't, T> {
     fn draw_in_render_window(&self, render_window: &mut RenderWindow) {
         if self.visible {
@@ -190,4 +500,31 @@ impl<'s, 't, T: 't> Drawable for Gui<'s, 't, T> {
             }
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use rsfml::graphics::Font;
+    use super::has_glyphs;
+
+    fn load(path: &str) -> Font {
+        Font::new_from_file(path).expect("could not load the font")
+    }
+
+    #[test]
+    fn present_glyphs_are_found() {
+        let font = load("media/fallback_font.ttf");
+        assert!(has_glyphs(&font, "Malmö Ångström", 16));
+        assert!(has_glyphs(&font, "Ж и ω", 16));
+    }
+
+    #[test]
+    fn missing_glyphs_are_detected() {
+        //the last private use plane is empty in every bundled font
+        for path in ["media/font.ttf", "media/text_font.ttf", "media/fallback_font.ttf"].iter() {
+            let font = load(*path);
+            assert!(has_glyphs(&font, "City", 16));
+            assert!(!has_glyphs(&font, "City\U0010FFFD", 16), "{} claims to have U+10FFFD", path);
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use std::io;
+use std::collections::HashMap;
+
+use rsfml::window::keyboard;
+use rsfml::window::keyboard::Key;
+
+pub static KEY_BINDINGS_PATH: &'static str = "keys.cfg";
+
+pub struct KeyBindings {
+    bindings: HashMap<&'static str, Key>
+}
+
+impl KeyBindings {
+    pub fn new() -> KeyBindings {
+        let mut bindings = HashMap::new();
+
+        bindings.insert("cancel", keyboard::Escape);
+        bindings.insert("quit", keyboard::Escape);
+        bindings.insert("quick_save", keyboard::F5);
+        bindings.insert("quick_load", keyboard::F9);
+        bindings.insert("toggle_log", keyboard::L);
+        bindings.insert("fast_forward", keyboard::F);
+        bindings.insert("toggle_animations", keyboard::A);
+        bindings.insert("sync_animations", keyboard::V);
+        bindings.insert("goods_overlay", keyboard::G);
+        bindings.insert("coverage_overlay", keyboard::C);
+        bindings.insert("edge_scrolling", keyboard::E);
+        bindings.insert("recenter", keyboard::Home);
+        bindings.insert("reset_taxes", keyboard::T);
+        bindings.insert("auto_taxes", keyboard::B);
+        bindings.insert("cycle_theme", keyboard::F2);
+        bindings.insert("console", keyboard::Tilde);
+        bindings.insert("pixel_zoom", keyboard::P);
+        bindings.insert("deliveries", keyboard::D);
+        bindings.insert("toggle_hud", keyboard::H);
+
+        KeyBindings {
+            bindings: bindings
+        }
+    }
+
+    //each line is on the form "action = Key", and unknown actions or keys are skipped
+    pub fn load(path: &Path) -> io::IoResult<KeyBindings> {
+        let mut key_bindings = KeyBindings::new();
+        let mut file = io::BufferedReader::new(try!(io::File::open(path)));
+
+        for line in file.lines() {
+            let line = try!(line);
+            let parts: Vec<&str> = line.as_slice().split('=').map(|part| part.trim()).collect();
+            if parts.len() != 2 {
+                continue;
+            }
+
+            match key_from_name(parts[1]) {
+                Some(key) => key_bindings.bind(parts[0], key),
+                None => {}
+            }
+        }
+
+        Ok(key_bindings)
+    }
+
+    pub fn bind(&mut self, action: &str, key: Key) {
+        match self.bindings.mut_iter().find(|&(name, _)| *name == action) {
+            Some((_, bound_key)) => *bound_key = key,
+            None => {}
+        }
+    }
+
+    pub fn key(&self, action: &str) -> Option<Key> {
+        self.bindings.iter().find(|&(name, _)| *name == action).map(|(_, &key)| key)
+    }
+
+    pub fn is_bound(&self, action: &str, key: Key) -> bool {
+        self.key(action) == Some(key)
+    }
+}
+
+fn key_from_name(name: &str) -> Option<Key> {
+    let key = match name {
+        "A" => keyboard::A, "B" => keyboard::B, "C" => keyboard::C, "D" => keyboard::D,
+        "E" => keyboard::E, "F" => keyboard::F, "G" => keyboard::G, "H" => keyboard::H,
+        "I" => keyboard::I, "J" => keyboard::J, "K" => keyboard::K, "L" => keyboard::L,
+        "M" => keyboard::M, "N" => keyboard::N, "O" => keyboard::O, "P" => keyboard::P,
+        "Q" => keyboard::Q, "R" => keyboard::R, "S" => keyboard::S, "T" => keyboard::T,
+        "U" => keyboard::U, "V" => keyboard::V, "W" => keyboard::W, "X" => keyboard::X,
+        "Y" => keyboard::Y, "Z" => keyboard::Z,
+        "F1" => keyboard::F1, "F2" => keyboard::F2, "F3" => keyboard::F3, "F4" => keyboard::F4,
+        "F5" => keyboard::F5, "F6" => keyboard::F6, "F7" => keyboard::F7, "F8" => keyboard::F8,
+        "F9" => keyboard::F9, "F10" => keyboard::F10, "F11" => keyboard::F11, "F12" => keyboard::F12,
+        "Escape" => keyboard::Escape,
+        "Space" => keyboard::Space,
+        "Return" => keyboard::Return,
+        "Tab" => keyboard::Tab,
+        "Home" => keyboard::Home,
+        "Tilde" => keyboard::Tilde,
+        _ => return None
+    };
+
+    Some(key)
+}
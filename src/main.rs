@@ -2,13 +2,23 @@
 
 extern crate rsfml;
 
+use std::os;
+
 mod game;
 mod tile;
 mod map;
 mod start_state;
 mod edit_state;
+mod victory_state;
 mod gui;
 mod city;
+mod records;
+mod saves;
+mod key_bindings;
+mod settings;
+mod projection;
+mod scenario;
+mod console;
 
 //For SFML on OS X
 #[cfg(target_os="macos")]
@@ -18,8 +28,23 @@ fn start(argc: int, argv: *const *const u8) -> int {
 }
 
 fn main() {
-    let mut game = game::Game::new().expect("unable to create game window");
+    //the tile size can be given as the first argument, to match larger art
+    let args = os::args();
+    let tile_size = match args.as_slice().get(1).and_then(|arg| from_str::<uint>(arg.as_slice())) {
+        Some(size) if size > 0 => size,
+        _ => game::DEFAULT_TILE_SIZE
+    };
+
+    let mut game = game::Game::new(tile_size).expect("unable to create game window");
     let state = start_state::StartState::new(&game).expect("unable to create start view");
     game.push_state(box state as Box<game::GameState>);
-    game.game_loop();
+
+    //the window is gone, so the terminal is the only place left to tell
+    match game.game_loop() {
+        Err(message) => {
+            let _ = std::io::stderr().write_line(message.as_slice());
+            os::set_exit_status(1);
+        },
+        Ok(()) => {}
+    }
 }
\ No newline at end of file
@@ -1,45 +1,306 @@
 use std::io;
 use std::mem::{swap, transmute};
 use std::iter;
-use std::iter::FilterMap;
-use std::slice::MutItems;
-use std::rand::{Rng, task_rng};
-use std::cmp::{min, max};
-use std::collections::HashMap;
+use std::iter::{FilterMap, Enumerate};
+use std::slice::{Items, MutItems};
+use std::rand::{Rng, SeedableRng, XorShiftRng, task_rng};
+use std::cmp::{min, max, Equal, Ordering};
+use std::collections::{HashMap, PriorityQueue};
 
 use rsfml::system::vector2::{Vector2f, Vector2i};
-use rsfml::graphics::{RenderWindow, Color};
+use rsfml::graphics::{RenderWindow, RenderStates, Color, VertexArray, Vertex, Quads};
 
 use tile;
-use tile::{Tile, TileType};
+use tile::{Tile, TileType, TextureRc};
+use projection;
 
+static SAVE_MAGIC: &'static str = "CITY";
+pub static SAVE_VERSION: u16 = 5;
+
+//the cost of each tile of a road route, and the extra cost for turning, to keep the routes straight
+static ROUTE_STEP_COST: uint = 2;
+static ROUTE_TURN_COST: uint = 1;
+
+#[deriving(Clone)]
 pub enum Selection {
     Deselected,
     Selected,
     Invalid
 }
 
+#[deriving(Clone, PartialEq)]
+pub enum Overlay {
+    NoOverlay,
+    GoodsOverlay
+}
+
+pub struct GenerationParams {
+    pub width: uint,
+    pub height: uint,
+    pub forest_chance: f32,
+    pub water_chance: f32,
+    pub void_chance: f32,
+    pub river: bool,
+    //places water and forest from a heightmap instead of rolling each tile on its own
+    pub elevation: bool,
+    pub lake_fraction: f32,
+    //only grass, so the whole map can be built on from the start
+    pub flat: bool
+}
+
+impl GenerationParams {
+    pub fn new() -> GenerationParams {
+        GenerationParams {
+            width: 50,
+            height: 50,
+            forest_chance: 0.2,
+            water_chance: 0.02,
+            void_chance: 0.01,
+            river: false,
+            elevation: true,
+            lake_fraction: 0.08,
+            flat: false
+        }
+    }
+}
+
+pub struct TileCounts {
+    pub void: uint,
+    pub grass: uint,
+    pub forest: uint,
+    pub water: uint,
+    pub residential: Vec<uint>,
+    pub commercial: uint,
+    pub industrial: uint,
+    pub road: uint,
+    pub airport: uint,
+    pub homes: uint,
+    pub jobs: uint
+}
+
+impl TileCounts {
+    pub fn new() -> TileCounts {
+        TileCounts {
+            void: 0,
+            grass: 0,
+            forest: 0,
+            water: 0,
+            residential: Vec::new(),
+            commercial: 0,
+            industrial: 0,
+            road: 0,
+            airport: 0,
+            homes: 0,
+            jobs: 0
+        }
+    }
+
+    pub fn total_residential(&self) -> uint {
+        self.residential.iter().fold(0, |total, &count| total + count)
+    }
+
+    //everything that was built, rather than left as nature
+    pub fn developed(&self) -> uint {
+        self.total_residential() + self.commercial + self.industrial + self.road + self.airport
+    }
+}
+
+pub struct RegionSummary {
+    pub tiles: uint,
+    pub roads: uint,
+    pub residents: f64,
+    pub homes: uint,
+    pub employees: f64,
+    pub jobs: uint,
+    //goods waiting in the industries and in the stores
+    pub stored_goods: u32,
+    pub buffered_goods: u32,
+    pub connected_to_edge: bool
+}
+
+impl RegionSummary {
+    pub fn describe(&self) -> Vec<String> {
+        vec![
+            format!("Region: {} tiles, {} roads", self.tiles, self.roads),
+            format!("Residents: {:.0}/{}", self.residents, self.homes),
+            format!("Employees: {:.0}/{}", self.employees, self.jobs),
+            format!("Goods: {} in industry, {} in stores", self.stored_goods, self.buffered_goods),
+            if self.connected_to_edge { "Connected to the outside".to_string() } else { "Not connected to the outside".to_string() }
+        ]
+    }
+}
+
+//a tile and the direction it was entered from, as index * 5 + direction, where 4 means the start
+#[deriving(PartialEq, Eq)]
+struct RouteStep {
+    cost: uint,
+    estimate: uint,
+    state: uint
+}
+
+//the priority queue pops the largest first, so the lowest estimate is made the largest
+impl PartialOrd for RouteStep {
+    fn partial_cmp(&self, other: &RouteStep) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for RouteStep {
+    fn cmp(&self, other: &RouteStep) -> Ordering {
+        other.estimate.cmp(&self.estimate)
+    }
+}
+
+//what a ground tile looks like in its batch, to tell when the batches have to be rebuilt.
+//animated tiles are never batched, so the frame only changes with the variant
+#[deriving(Clone, PartialEq)]
+struct BatchKey {
+    texture: uint,
+    frame: (i32, i32),
+    color: (u8, u8, u8)
+}
+
+//all ground tiles that share a texture, as one quad each
+#[deriving(Clone)]
+struct TileBatch {
+    texture: TextureRc,
+    texture_id: uint,
+    vertices: VertexArray
+}
+
+impl TileBatch {
+    fn new(texture: TextureRc, texture_id: uint) -> TileBatch {
+        let mut vertices = VertexArray::new().expect("unable to create vertex array");
+        vertices.set_primitive_type(Quads);
+
+        TileBatch {
+            texture: texture,
+            texture_id: texture_id,
+            vertices: vertices
+        }
+    }
+
+    fn add_tile(&mut self, tile: &Tile) {
+        let position = tile.sprite.get_position();
+        let frame = tile.sprite.get_texture_rect();
+        let color = tile.sprite.get_color();
+
+        for &(x, y) in [(0, 0), (frame.width, 0), (frame.width, frame.height), (0, frame.height)].iter() {
+            self.vertices.append(&Vertex::new(
+                &position.add(&Vector2f::new(x as f32, y as f32)),
+                &color,
+                &Vector2f::new((frame.left + x) as f32, (frame.top + y) as f32)
+            ));
+        }
+    }
+
+    fn draw(&self, window: &mut RenderWindow) {
+        let texture = self.texture.borrow();
+        let mut states = RenderStates::default();
+        states.texture = Some(texture.deref());
+        window.draw_with_renderstates(&self.vertices, &mut states);
+    }
+}
+
+#[deriving(Clone)]
 pub struct Map {
     width: uint,
     height: uint,
     tiles: Vec<(Tile, uint, Selection)>,
     tile_size: uint,
     pub num_selected: uint,
-    num_regions: Vec<uint>
+    pub overlay: Overlay,
+    pub wrap_edges: bool,
+    num_regions: Vec<uint>,
+    //the tile indices of each region label, for every region type
+    region_index: Vec<HashMap<uint, Vec<uint>>>,
+    elevation: Vec<u8>,
+    batches: Vec<TileBatch>,
+    batch_keys: Vec<Option<BatchKey>>,
+    batched_overlay: Overlay,
+    //set by everything that hands out or changes tiles, to look at the batched ones again
+    tiles_changed: bool
 }
 
 impl Map {
-    pub fn new_generated(tile_size: uint, tile_atlas: &HashMap<&'static str, Tile>) -> Map {
-        let width = 50;
-        let height = 50;
+    //a map without any tiles, to generate or load into
+    pub fn empty(tile_size: uint) -> Map {
+        Map {
+            width: 0,
+            height: 0,
+            tiles: Vec::new(),
+            tile_size: tile_size,
+            num_selected: 0,
+            overlay: NoOverlay,
+            wrap_edges: false,
+            num_regions: vec![0],
+            region_index: vec![HashMap::new()],
+            elevation: Vec::new(),
+            batches: Vec::new(),
+            batch_keys: Vec::new(),
+            batched_overlay: NoOverlay,
+            tiles_changed: true
+        }
+    }
 
+    pub fn new_generated(tile_size: uint, params: &GenerationParams, tile_atlas: &HashMap<&'static str, Tile>) -> Map {
+        let mut map = Map::empty(tile_size);
+
+        let seed = [task_rng().gen(), task_rng().gen(), task_rng().gen(), task_rng().gen()];
+        map.regenerate(seed, params, tile_atlas);
+        map
+    }
+
+    pub fn regenerate(&mut self, seed: [u32, ..4], params: &GenerationParams, tile_atlas: &HashMap<&'static str, Tile>) {
+        let mut rng: XorShiftRng = SeedableRng::from_seed(seed);
         let mut tiles = Vec::new();
 
-        for _ in range(0u, width * height) {
-            let tile = if 0.2f32 > task_rng().gen() {
+        let river = if params.river && !params.flat {
+            carve_river(&mut rng, params.width, params.height)
+        } else {
+            Vec::from_elem(params.width * params.height, false)
+        };
+
+        let elevation = if params.elevation && !params.flat {
+            make_heightmap(&mut rng, params.width, params.height)
+        } else {
+            Vec::from_elem(params.width * params.height, 0.0f32)
+        };
+
+        //the lowest tiles become lakes, so the water level is picked from the sorted heights
+        let water_level = if params.elevation && !params.flat && params.width * params.height > 0 {
+            let mut sorted = elevation.clone();
+            sorted.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Equal));
+            let lake_tiles = (sorted.len() as f32 * params.lake_fraction) as uint;
+            if lake_tiles > 0 { sorted[lake_tiles - 1] } else { -1.0 }
+        } else {
+            -1.0
+        };
+
+        for index in range(0u, params.width * params.height) {
+            let height = elevation[index];
+            let tile = if params.flat {
+                tile_atlas.find(&"grass").expect("grass tile was not loaded").clone()
+            } else if river[index] {
+                tile_atlas.find(&"water").expect("water tile was not loaded").clone()
+            } else if params.elevation && height <= water_level {
+                tile_atlas.find(&"water").expect("water tile was not loaded").clone()
+            } else if params.elevation {
+                //forests grow on the slopes between the valleys and the hilltops
+                let forest_chance = if height > 0.45 && height < 0.75 { params.forest_chance * 3.0 } else { params.forest_chance * 0.25 };
+                if forest_chance > rng.gen() {
+                    tile_atlas.find(&"forest").expect("forest tile was not loaded").clone()
+                } else if params.void_chance > rng.gen() {
+                    tile_atlas.find(&"void").expect("void tile was not loaded").clone()
+                } else {
+                    tile_atlas.find(&"grass").expect("grass tile was not loaded").clone()
+                }
+            } else if params.forest_chance > rng.gen() {
                 tile_atlas.find(&"forest").expect("forest tile was not loaded").clone()
-            } else if 0.02f32 > task_rng().gen() {
+            } else if params.water_chance > rng.gen() {
                 tile_atlas.find(&"water").expect("water tile was not loaded").clone()
+            } else if params.void_chance > rng.gen() {
+                tile_atlas.find(&"void").expect("void tile was not loaded").clone()
             } else {
                 tile_atlas.find(&"grass").expect("grass tile was not loaded").clone()
             };
@@ -47,134 +308,446 @@ impl Map {
             tiles.push((tile, 255, Deselected));
         }
 
-        Map {
-            width: width,
-            height: height,
-            tiles: tiles,
-            tile_size: tile_size,
-            num_selected: 0,
-            num_regions: vec![0]
-        }
+        self.width = params.width;
+        self.height = params.height;
+        self.tiles = tiles;
+        self.tiles_changed = true;
+        self.num_selected = 0;
+        self.num_regions = vec![0];
+        self.region_index = vec![HashMap::new()];
+        self.elevation = elevation.iter().map(|&height| (height * 255.0) as u8).collect();
+    }
+
+    //between 0 for the lowest land and 255 for the highest, or None outside the map
+    pub fn elevation_at(&self, pos: &Vector2i) -> Option<u8> {
+        self.index_of(pos).map(|index| self.elevation[index])
     }
 
     pub fn load(&mut self, path: &Path, tile_atlas: &HashMap<&'static str, Tile>) -> io::IoResult<()> {
-        let mut file = try!(io::File::open(path));
-        self.width = try!(file.read_be_u32()) as uint;
-        self.height = try!(file.read_be_u32()) as uint;
+        let mut file = io::BufferedReader::new(try!(io::File::open(path)));
+        self.read_from(&mut file, tile_atlas)
+    }
 
-        let mut tiles = Vec::new();
+    //the map is only replaced if the whole file could be read and validated
+    pub fn read_from<R: Reader>(&mut self, file: &mut R, tile_atlas: &HashMap<&'static str, Tile>) -> io::IoResult<()> {
+        *self = try!(self.loaded_from(file, tile_atlas));
+        Ok(())
+    }
 
-        for _ in range(0u, self.width * self.height) {
-            let mut tile = match try!(file.read_u8()) {
-                0 | 1 => tile_atlas.find(&"grass").unwrap().clone(),
-                2 => tile_atlas.find(&"forest").unwrap().clone(),
-                3 => tile_atlas.find(&"water").unwrap().clone(),
-                4 => {
-                    let mut tile = tile_atlas.find(&"residential").unwrap().clone();
-                    tile.set_population(try!(file.read_be_f64()));
-                    tile
-                },
-                5 => {
-                    let mut tile = tile_atlas.find(&"commercial").unwrap().clone();
-                    tile.set_population(try!(file.read_be_f64()));
-                    tile
-                },
-                6 => {
-                    let mut tile = tile_atlas.find(&"industrial").unwrap().clone();
-                    tile.set_population(try!(file.read_be_f64()));
-                    tile.set_production(try!(file.read_be_u32()));
-                    tile.set_stored_goods(try!(file.read_be_u32()));
-                    tile
-                },
-                7 => tile_atlas.find(&"road").unwrap().clone(),
-                n => return Err(io::IoError {
-                    kind: io::OtherIoError,
-                    desc: "invalid tile type in map file",
-                    detail: Some(format!("found type number {}", n))
-                })
-            };
+    //reads a new map with the same settings as this one, leaving this one as it is
+    pub fn loaded_from<R: Reader>(&self, file: &mut R, tile_atlas: &HashMap<&'static str, Tile>) -> io::IoResult<Map> {
+        let header = try!(file.read_exact(SAVE_MAGIC.len()));
 
-            tile.variant = try!(file.read_be_u32()) as uint;
+        //maps from before the header was added start right away with the width, and are read as version 0
+        if header.as_slice() != SAVE_MAGIC.as_bytes() {
+            let width = header.iter().fold(0u32, |width, &byte| (width << 8) | byte as u32);
+            return self.read_tiles(file, width as uint, 0, tile_atlas);
+        }
 
-            let num_regions = try!(file.read_be_u32()) as uint;
-            let mut regions = Vec::new();
-            for _ in range(0u, num_regions) {
-                regions.push(try!(file.read_be_u32()) as uint);
-            }
-            tile.regions = regions;
+        match try!(file.read_be_u16()) {
+            version if version >= 1 && version <= SAVE_VERSION => {
+                let width = try!(file.read_be_u32()) as uint;
+                self.read_tiles(file, width, version, tile_atlas)
+            },
+            version => Err(unsupported_version(version))
+        }
+    }
 
-            tiles.push((tile, 255, Deselected));
+    fn read_tiles<R: Reader>(&self, file: &mut R, width: uint, version: u16, tile_atlas: &HashMap<&'static str, Tile>) -> io::IoResult<Map> {
+        let mut map = Map::empty(self.tile_size);
+        map.overlay = self.overlay.clone();
+        map.wrap_edges = self.wrap_edges;
+        map.num_regions = self.num_regions.iter().map(|_| 0).collect();
+        map.region_index = self.num_regions.iter().map(|_| HashMap::new()).collect();
+
+        map.width = width;
+        map.height = try!(file.read_be_u32()) as uint;
+
+        for _ in range(0u, map.width * map.height) {
+            map.tiles.push((try!(Tile::read_from(file, version, tile_atlas)), 255, Deselected));
         }
 
-        self.tiles = tiles;
+        //older maps are flat
+        map.elevation = if version >= 3 {
+            try!(file.read_exact(map.width * map.height))
+        } else {
+            Vec::from_elem(map.width * map.height, 0u8)
+        };
+
+        try!(map.validate().map_err(invalid_map));
+        Ok(map)
+    }
+
+    //checks what the rest of the game assumes about the tiles, to catch corrupt or incompatible files
+    pub fn validate(&self) -> Result<(), String> {
+        if self.tiles.len() != self.width * self.height {
+            return Err(format!("expected {} tiles for a {}x{} map, found {}", self.width * self.height, self.width, self.height, self.tiles.len()));
+        }
+
+        if self.elevation.len() != self.tiles.len() {
+            return Err(format!("expected {} elevation values, found {}", self.tiles.len(), self.elevation.len()));
+        }
+
+        for (index, &(ref tile, _, _)) in self.tiles.iter().enumerate() {
+            let (x, y) = (index % self.width, index / self.width);
+
+            if tile.variant >= tile.variant_count() {
+                return Err(format!("the {} at ({}, {}) has variant {}, but only {} exist", tile.tile_type, x, y, tile.variant, tile.variant_count()));
+            }
+
+            match tile.tile_type {
+                tile::Residential {max_levels, ..} | tile::Commercial {max_levels, ..} | tile::Industrial {max_levels, ..} if tile.variant > max_levels => {
+                    return Err(format!("the {} at ({}, {}) is level {}, above the maximum of {}", tile.tile_type, x, y, tile.variant + 1, max_levels + 1));
+                },
+                _ => {}
+            }
+
+            if tile.regions.len() != self.num_regions.len() {
+                return Err(format!("the tile at ({}, {}) has {} region labels, expected {}", x, y, tile.regions.len(), self.num_regions.len()));
+            }
+
+            match tile.anchor {
+                Some((anchor_x, anchor_y)) if anchor_x >= self.width || anchor_y >= self.height => {
+                    return Err(format!("the tile at ({}, {}) belongs to a building outside the map", x, y));
+                },
+                _ => {}
+            }
+        }
 
         Ok(())
     }
 
     pub fn save(&self, path: &Path) -> io::IoResult<()> {
-        let mut file = try!(io::File::create(path));
+        let mut file = io::BufferedWriter::new(try!(io::File::create(path)));
+        try!(self.write_to(&mut file));
+        file.flush()
+    }
 
+    pub fn write_to<W: Writer>(&self, file: &mut W) -> io::IoResult<()> {
+        try!(write_header(file));
         try!(file.write_be_u32(self.width as u32));
         try!(file.write_be_u32(self.height as u32));
 
         for &(ref tile, _resources, _) in self.tiles.iter() {
-            match tile.tile_type {
-                tile::Void => try!(file.write_u8(0)),
-                tile::Grass => try!(file.write_u8(1)),
-                tile::Forest => try!(file.write_u8(2)),
-                tile::Water => try!(file.write_u8(3)),
-                tile::Residential {population, ..} => {
-                    try!(file.write_u8(4));
-                    try!(file.write_be_f64(population));
-                },
-                tile::Commercial {population, ..} => {
-                    try!(file.write_u8(5));
-                    try!(file.write_be_f64(population));
-                },
-                tile::Industrial {population, production, stored_goods, ..} => {
-                    try!(file.write_u8(6));
-                    try!(file.write_be_f64(population));
-                    try!(file.write_be_u32(production));
-                    try!(file.write_be_u32(stored_goods));
-                },
-                tile::Road => try!(file.write_u8(7))
-            }
+            try!(tile.write_to(file));
+        }
+
+        file.write(self.elevation.as_slice())
+    }
+
+    //writes only the tiles that differ from the baseline, which has to be saved separately
+    pub fn save_delta(&self, path: &Path, baseline: &Map) -> io::IoResult<()> {
+        let mut file = io::BufferedWriter::new(try!(io::File::create(path)));
+        try!(self.write_delta_to(&mut file, baseline));
+        file.flush()
+    }
+
+    pub fn write_delta_to<W: Writer>(&self, file: &mut W, baseline: &Map) -> io::IoResult<()> {
+        if self.width != baseline.width || self.height != baseline.height {
+            return Err(io::IoError {
+                kind: io::InvalidInput,
+                desc: "the baseline map has a different size",
+                detail: Some(format!("expected {}x{}, found {}x{}", self.width, self.height, baseline.width, baseline.height))
+            });
+        }
 
-            try!(file.write_be_u32(tile.variant as u32));
-            try!(file.write_be_u32(tile.regions.len() as u32));
-            for &region in tile.regions.iter() {
-                try!(file.write_be_u32(region as u32));
+        let mut changes = Vec::new();
+        for (index, (&(ref tile, _, _), &(ref old_tile, _, _))) in self.tiles.iter().zip(baseline.tiles.iter()).enumerate() {
+            let new_data = try!(tile_data(tile));
+            if new_data != try!(tile_data(old_tile)) {
+                changes.push((index, new_data));
             }
         }
 
+        try!(write_header(file));
+        try!(file.write_be_u32(self.width as u32));
+        try!(file.write_be_u32(self.height as u32));
+        try!(file.write_be_u32(changes.len() as u32));
+
+        for &(index, ref data) in changes.iter() {
+            try!(file.write_be_u32(index as u32));
+            try!(file.write(data.as_slice()));
+        }
+
+        Ok(())
+    }
+
+    //applies a delta on top of the baseline it was saved against
+    pub fn apply_delta(&mut self, path: &Path, tile_atlas: &HashMap<&'static str, Tile>) -> io::IoResult<()> {
+        let mut file = io::BufferedReader::new(try!(io::File::open(path)));
+        self.read_delta_from(&mut file, tile_atlas)
+    }
+
+    //the map is only changed if the whole delta could be read and the result validated
+    pub fn read_delta_from<R: Reader>(&mut self, file: &mut R, tile_atlas: &HashMap<&'static str, Tile>) -> io::IoResult<()> {
+        *self = try!(self.delta_applied_from(file, tile_atlas));
+        self.tiles_changed = true;
         Ok(())
     }
 
+    //reads a delta and returns a copy of this map with it applied, leaving this one as it is
+    pub fn delta_applied_from<R: Reader>(&self, file: &mut R, tile_atlas: &HashMap<&'static str, Tile>) -> io::IoResult<Map> {
+        let header = try!(file.read_exact(SAVE_MAGIC.len()));
+        if header.as_slice() != SAVE_MAGIC.as_bytes() {
+            return Err(io::IoError {
+                kind: io::OtherIoError,
+                desc: "not a map delta file",
+                detail: None
+            });
+        }
+
+        let version = match try!(file.read_be_u16()) {
+            version if version >= 1 && version <= SAVE_VERSION => version,
+            version => return Err(unsupported_version(version))
+        };
+
+        let width = try!(file.read_be_u32()) as uint;
+        let height = try!(file.read_be_u32()) as uint;
+
+        if width != self.width || height != self.height {
+            return Err(io::IoError {
+                kind: io::OtherIoError,
+                desc: "the delta does not match the baseline map",
+                detail: Some(format!("expected {}x{}, found {}x{}", self.width, self.height, width, height))
+            });
+        }
+
+        let num_changes = try!(file.read_be_u32()) as uint;
+        let mut changes = Vec::new();
+        for _ in range(0u, num_changes) {
+            let index = try!(file.read_be_u32()) as uint;
+            if index >= self.tiles.len() {
+                return Err(io::IoError {
+                    kind: io::OtherIoError,
+                    desc: "invalid tile index in delta file",
+                    detail: Some(format!("found index {}", index))
+                });
+            }
+
+            changes.push((index, try!(Tile::read_from(file, version, tile_atlas))));
+        }
+
+        let mut map = self.clone();
+        map.clear_selected();
+        for (index, tile) in changes.move_iter() {
+            *map.tiles.get_mut(index) = (tile, 255, Deselected);
+        }
+
+        try!(map.validate().map_err(invalid_map));
+        Ok(map)
+    }
+
     pub fn size(&self) -> (uint, uint) {
         (self.width, self.height)
     }
 
-    pub fn draw(&mut self, window: &mut RenderWindow, dt: f32) {
+    pub fn count_tiles(&self) -> TileCounts {
+        let mut counts = TileCounts::new();
+
+        for &(ref tile, _, _) in self.tiles.iter() {
+            match tile.tile_type {
+                tile::Void => counts.void += 1,
+                tile::Grass => counts.grass += 1,
+                tile::Forest => counts.forest += 1,
+                tile::Water => counts.water += 1,
+                tile::Residential {max_pop_per_level, ..} => {
+                    while counts.residential.len() <= tile.variant {
+                        counts.residential.push(0);
+                    }
+                    *counts.residential.get_mut(tile.variant) += 1;
+                    counts.homes += max_pop_per_level * (tile.variant + 1);
+                },
+                tile::Commercial {max_pop_per_level, ..} => {
+                    counts.commercial += 1;
+                    counts.jobs += max_pop_per_level * (tile.variant + 1);
+                },
+                tile::Industrial {max_pop_per_level, ..} => {
+                    counts.industrial += 1;
+                    counts.jobs += max_pop_per_level * (tile.variant + 1);
+                },
+                tile::Road => counts.road += 1,
+                tile::Airport => counts.airport += 1
+            }
+        }
+
+        counts
+    }
+
+    pub fn draw(&mut self, window: &mut RenderWindow, dt: f32, animate: bool) {
+        let mut batches_changed = self.batch_keys.len() != self.tiles.len();
+        if batches_changed {
+            self.batch_keys = Vec::from_elem(self.tiles.len(), None);
+            self.tiles_changed = true;
+        }
+
+        if self.batched_overlay != self.overlay {
+            self.batched_overlay = self.overlay.clone();
+            self.tiles_changed = true;
+        }
+
+        let tiles_changed = self.tiles_changed;
+        self.tiles_changed = false;
+
         for y in range(0, self.height) {
             for x in range(0, self.width) {
-                let pos = Vector2f::new(
-                    ((x - y) * self.tile_size + self.width * self.tile_size) as f32,
-                    ((x + y) * self.tile_size) as f32 * 0.5
-                );
-                let &(ref mut tile, _, ref selection) = self.tiles.get_mut(y * self.width + x);
+                //batched tiles are static, so they stay as they are until something changes
+                let index = y * self.width + x;
+                if !tiles_changed && self.batch_keys[index].is_some() {
+                    continue;
+                }
+
+                let key = match self.drawn_at(x, y) {
+                    Some(drawn) => self.prepare_tile(drawn, dt, animate),
+                    None => None
+                };
+
+                if self.batch_keys[index] != key {
+                    *self.batch_keys.get_mut(index) = key;
+                    batches_changed = true;
+                }
+            }
+        }
+
+        if batches_changed {
+            self.rebuild_batches();
+        }
+
+        //the ground is flat, so it can go below everything else
+        for batch in self.batches.iter() {
+            batch.draw(window);
+        }
+
+        for (index, key) in self.batch_keys.iter().enumerate() {
+            if key.is_some() {
+                let (ref tile, _, _) = self.tiles[index];
+                tile.draw_direction(window);
+            }
+        }
 
-                match selection {
-                    &Selected | &Invalid => tile.sprite.set_color(&Color::new_RGB(0x7d, 0x7d, 0x7d)),
-                    _ => tile.sprite.set_color(&Color::new_RGB(0xff, 0xff, 0xff))
+        //draw one diagonal at a time, back to front, so tall tiles overlap the ones behind them
+        for depth in range(0, self.width + self.height - 1) {
+            let first_x = if depth >= self.height { depth - (self.height - 1) } else { 0 };
+            let last_x = min(depth, self.width - 1);
+
+            for x in range(first_x, last_x + 1) {
+                if self.batch_keys[(depth - x) * self.width + x].is_some() {
+                    continue;
                 }
 
-                tile.sprite.set_position(&pos);
-                tile.draw(window, dt);
+                let (x, y, _, _) = match self.drawn_at(x, depth - x) {
+                    Some(drawn) => drawn,
+                    None => continue
+                };
+
+                let (ref tile, _, _) = self.tiles[y * self.width + x];
+                tile.draw(window);
+            }
+        }
+    }
+
+    //places, colors and animates a tile, and returns its batch key if it's drawn as ground
+    fn prepare_tile(&mut self, (x, y, footprint_width, footprint_height): (uint, uint, uint, uint), dt: f32, animate: bool) -> Option<BatchKey> {
+        //a building's sprite is stretched to cover its whole footprint
+        let pos = Vector2f::new(
+            self.tile_to_screen(x, y + footprint_height - 1).x,
+            self.tile_to_screen(x, y).y
+        );
+        let overlay = self.overlay;
+        let &(ref mut tile, _, ref selection) = self.tiles.get_mut(y * self.width + x);
+
+        //the zone textures have one row per variant and no empty buildings, so abandoned
+        //buildings keep their sprite and are faded towards brown instead
+        let state_color = if tile.abandoned {
+            Color::new_RGB(0xa0, 0x8c, 0x78)
+        } else if tile.undeveloped {
+            Color::new_RGB(0x90, 0xa8, 0xd0)
+        } else if tile.congestion() > 0.5 {
+            let fade = (0xff as f32 * (1.5 - tile.congestion())) as u8;
+            Color::new_RGB(0xff, fade, fade)
+        } else {
+            match overlay {
+                GoodsOverlay if tile.tile_type.lacks_goods() => Color::new_RGB(0xff, 0x80, 0x80),
+                _ => Color::new_RGB(0xff, 0xff, 0xff)
+            }
+        };
+
+        //the overlay tint is mixed in, and the selection darkens whatever is below it
+        let color = match tile.tint {
+            Some(ref tint) => modulate(&state_color, tint),
+            None => state_color
+        };
+        let color = match selection {
+            &Selected | &Invalid => modulate(&color, &Color::new_RGB(0x7d, 0x7d, 0x7d)),
+            _ => color
+        };
+        tile.sprite.set_color(&color);
+
+        tile.sprite.set_position(&pos);
+        tile.sprite.set_scale(&Vector2f::new(footprint_width as f32, footprint_height as f32));
+        tile.update_frame(dt, animate);
+
+        if footprint_width * footprint_height > 1 || !tile.is_ground() {
+            return None;
+        }
+
+        tile.sprite.get_texture().map(|texture| {
+            let frame = tile.sprite.get_texture_rect();
+            let color = tile.sprite.get_color();
+
+            BatchKey {
+                texture: texture.deref() as *const _ as uint,
+                frame: (frame.left, frame.top),
+                color: (color.red, color.green, color.blue)
             }
+        })
+    }
+
+    fn rebuild_batches(&mut self) {
+        let mut batches: Vec<TileBatch> = Vec::new();
+
+        for (index, key) in self.batch_keys.iter().enumerate() {
+            let texture_id = match *key {
+                Some(ref key) => key.texture,
+                None => continue
+            };
+
+            let (ref tile, _, _) = self.tiles[index];
+            let batch_index = match batches.iter().position(|batch| batch.texture_id == texture_id) {
+                Some(batch_index) => batch_index,
+                None => {
+                    batches.push(TileBatch::new(tile.sprite.get_texture().unwrap(), texture_id));
+                    batches.len() - 1
+                }
+            };
+
+            batches.get_mut(batch_index).add_tile(tile);
         }
+
+        self.batches = batches;
+    }
+
+    pub fn tile_to_screen(&self, x: uint, y: uint) -> Vector2f {
+        projection::tile_to_screen(x, y, self.tile_size, self.width)
+    }
+
+    pub fn tile_center(&self, index: uint) -> Vector2f {
+        let corner = self.tile_to_screen(index % self.width, index / self.width);
+        Vector2f::new(corner.x + self.tile_size as f32, corner.y + self.tile_size as f32 * 0.5)
+    }
+
+    pub fn screen_to_tile(&self, pos: &Vector2f) -> Vector2i {
+        projection::screen_to_tile(pos, self.tile_size, self.width)
+    }
+
+    pub fn center(&self) -> Vector2f {
+        Vector2f::new(
+            (self.width * self.tile_size) as f32,
+            (self.height * self.tile_size) as f32 * 0.5
+        )
     }
 
     pub fn update_direction(&mut self, tile_type: TileType) {
+        self.tiles_changed = true;
         for y in range(0, self.height) {
             for x in range(0, self.width) {
                 {
@@ -186,44 +759,9 @@ impl Map {
 
                 let mut adjecent = [[false, ..3], ..3];
 
-                if x > 0 {
-                    if y > 0 {
-                        let (ref tile, _, _) = self.tiles[(y - 1) * self.width + x - 1];
-                        adjecent[0][0] = tile.tile_type.similar_to(&tile_type);
-                    }
-
-                    let (ref tile, _, _) = self.tiles[y* self.width + x - 1];
-                    adjecent[1][0] = tile.tile_type.similar_to(&tile_type);
-
-                    if y < self.height - 1 {
-                        let (ref tile, _, _) = self.tiles[(y + 1) * self.width + x - 1];
-                        adjecent[2][0] = tile.tile_type.similar_to(&tile_type);
-                    }
-                }
-
-                if y > 0 {
-                    let (ref tile, _, _) = self.tiles[(y - 1) * self.width + x];
-                    adjecent[0][1] = tile.tile_type.similar_to(&tile_type);
-                }
-
-                if y < self.height - 1 {
-                    let (ref tile, _, _) = self.tiles[(y + 1) * self.width + x];
-                    adjecent[2][1] = tile.tile_type.similar_to(&tile_type);
-                }
-
-                if x < self.width - 1 {
-                    if y > 0 {
-                        let (ref tile, _, _) = self.tiles[(y - 1) * self.width + x + 1];
-                        adjecent[0][2] = tile.tile_type.similar_to(&tile_type);
-                    }
-
-                    let (ref tile, _, _) = self.tiles[y* self.width + x + 1];
-                    adjecent[1][2] = tile.tile_type.similar_to(&tile_type);
-
-                    if y < self.height - 1 {
-                        let (ref tile, _, _) = self.tiles[(y + 1) * self.width + x + 1];
-                        adjecent[2][2] = tile.tile_type.similar_to(&tile_type);
-                    }
+                for &(offset, position) in self.neighbor_positions(&Vector2i::new(x as i32, y as i32), true).iter() {
+                    let (ref tile, _, _) = self.tiles[position.y as uint * self.width + position.x as uint];
+                    adjecent[(offset.y + 1) as uint][(offset.x + 1) as uint] = tile.tile_type.similar_to(&tile_type);
                 }
 
                 let &(ref mut tile, _, _) = self.tiles.get_mut(y * self.width + x);
@@ -263,25 +801,77 @@ impl Map {
         }
     }
 
-    fn depth_first_search(&mut self, whitelisted: &mut |&TileType| -> bool, position: Vector2i, label: uint, region_type: uint) {
-        if position.x < 0 || position.x >= self.width as i32 || position.y < 0 || position.y >= self.height as i32 {
-            return
+    fn resolve_position(&self, pos: Vector2i) -> Option<Vector2i> {
+        let width = self.width as i32;
+        let height = self.height as i32;
+
+        if self.wrap_edges {
+            Some(Vector2i::new(((pos.x % width) + width) % width, ((pos.y % height) + height) % height))
+        } else if pos.x >= 0 && pos.x < width && pos.y >= 0 && pos.y < height {
+            Some(pos)
+        } else {
+            None
         }
+    }
 
-        let found = {
-            let &(ref mut tile, _, _) = self.tiles.get_mut(position.y as uint * self.width + position.x as uint);
-            if tile.regions[region_type] != 0 {
-                return
-            }
-            if (*whitelisted)(&tile.tile_type) {
-                *tile.regions.get_mut(region_type) = label;
-                true
-            } else {
-                false
+    fn neighbor_positions(&self, pos: &Vector2i, diagonal: bool) -> Vec<(Vector2i, Vector2i)> {
+        let mut positions = Vec::new();
+
+        for dy in range(-1i32, 2) {
+            for dx in range(-1i32, 2) {
+                if (dx == 0 && dy == 0) || (!diagonal && dx != 0 && dy != 0) {
+                    continue;
+                }
+
+                match self.resolve_position(Vector2i::new(pos.x + dx, pos.y + dy)) {
+                    Some(neighbor) => positions.push((Vector2i::new(dx, dy), neighbor)),
+                    None => {}
+                }
             }
-        };
+        }
 
-        
+        positions
+    }
+
+    pub fn neighbors(&self, pos: &Vector2i, diagonal: bool) -> Vec<(Vector2i, &Tile)> {
+        self.neighbor_positions(pos, diagonal).move_iter().map(|(_, neighbor)| {
+            let &(ref tile, _, _) = &self.tiles[neighbor.y as uint * self.width + neighbor.x as uint];
+            (neighbor, tile)
+        }).collect()
+    }
+
+    pub fn is_adjacent_to(&self, pos: &Vector2i, tile_type: &TileType) -> bool {
+        self.neighbors(pos, true).iter().any(|&(_, ref tile)| tile.tile_type.similar_to(tile_type))
+    }
+
+    //true if any of the selected tiles is next to the tile type, since a building only needs one of its tiles there
+    pub fn selection_adjacent_to(&self, tile_type: &TileType) -> bool {
+        self.tiles.iter().enumerate().any(|(index, &(_, _, selection))| match selection {
+            Selected => self.is_adjacent_to(&Vector2i::new((index % self.width) as i32, (index / self.width) as i32), tile_type),
+            _ => false
+        })
+    }
+
+    fn depth_first_search(&mut self, whitelisted: &mut |&TileType| -> bool, position: Vector2i, label: uint, region_type: uint) {
+        let position = match self.resolve_position(position) {
+            Some(position) => position,
+            None => return
+        };
+
+        let found = {
+            let &(ref mut tile, _, _) = self.tiles.get_mut(position.y as uint * self.width + position.x as uint);
+            if tile.regions[region_type] != 0 {
+                return
+            }
+            if (*whitelisted)(&tile.tile_type) {
+                *tile.regions.get_mut(region_type) = label;
+                true
+            } else {
+                false
+            }
+        };
+
+        
         if found {
             self.depth_first_search(whitelisted, position.add(&Vector2i::new(-1,  0)), label, region_type);
             self.depth_first_search(whitelisted, position.add(&Vector2i::new( 0,  1)), label, region_type);
@@ -317,9 +907,235 @@ impl Map {
         }
 
         *self.num_regions.get_mut(region_type) = regions;
+        self.rebuild_region_index(region_type);
+    }
+
+    //the indices of the tiles with the label, in about the same time as it takes to visit them.
+    //label 0 lists the tiles outside of any region
+    pub fn region_tiles(&self, region_type: uint, label: uint) -> Items<uint> {
+        static NO_TILES: [uint, ..0] = [];
+
+        match self.region_index[region_type].find(&label) {
+            Some(indices) => indices.iter(),
+            None => NO_TILES.iter()
+        }
+    }
+
+    fn rebuild_region_index(&mut self, region_type: uint) {
+        let mut index: HashMap<uint, Vec<uint>> = HashMap::new();
+
+        for (tile_index, &(ref tile, _, _)) in self.tiles.iter().enumerate() {
+            index.find_or_insert_with(tile.regions[region_type], |_| Vec::new()).push(tile_index);
+        }
+
+        *self.region_index.get_mut(region_type) = index;
+    }
+
+    //patches the region labels around a single changed tile, and falls back to
+    //find_connected_regions when removing the tile could split its region
+    pub fn update_regions_incremental(&mut self, pos: &Vector2i, whitelisted: |&TileType| -> bool, region_type: uint) {
+        let index = match self.index_of(pos) {
+            Some(index) => index,
+            None => return
+        };
+
+        //wrapped edges can make the same tile a neighbor from two sides on a narrow map
+        let mut neighbors: Vec<uint> = self.neighbor_positions(pos, false).iter()
+            .map(|&(_, neighbor)| neighbor.y as uint * self.width + neighbor.x as uint)
+            .filter(|&neighbor| neighbor != index)
+            .collect();
+        neighbors.sort();
+        neighbors.dedup();
+
+        let (old_label, included) = {
+            let (ref tile, _, _) = self.tiles[index];
+            (tile.regions[region_type], whitelisted(&tile.tile_type))
+        };
+
+        if included {
+            //the tile was already connected, so nothing changed
+            if old_label != 0 {
+                return;
+            }
+
+            let mut labels: Vec<uint> = neighbors.iter().filter_map(|&neighbor| {
+                let (ref tile, _, _) = self.tiles[neighbor];
+                match tile.regions[region_type] {
+                    0 => None,
+                    label => Some(label)
+                }
+            }).collect();
+            labels.sort();
+            labels.dedup();
+
+            let label = match labels.as_slice().head() {
+                Some(&label) => label,
+                None => {
+                    let label = self.num_regions[region_type];
+                    *self.num_regions.get_mut(region_type) += 1;
+                    label
+                }
+            };
+
+            {
+                let &(ref mut tile, _, _) = self.tiles.get_mut(index);
+                *tile.regions.get_mut(region_type) = label;
+            }
+
+            {
+                let region_index = self.region_index.get_mut(region_type);
+                match region_index.find_mut(&0) {
+                    Some(indices) => indices.retain(|&tile_index| tile_index != index),
+                    None => {}
+                }
+                region_index.find_or_insert_with(label, |_| Vec::new()).push(index);
+            }
+
+            //the new tile joins several regions into one
+            if labels.len() > 1 {
+                let merged = labels.slice_from(1);
+                for &(ref mut tile, _, _) in self.tiles.mut_iter() {
+                    if merged.contains(&tile.regions[region_type]) {
+                        *tile.regions.get_mut(region_type) = label;
+                    }
+                }
+
+                let region_index = self.region_index.get_mut(region_type);
+                for merged_label in merged.iter() {
+                    match region_index.pop(merged_label) {
+                        Some(indices) => region_index.find_or_insert_with(label, |_| Vec::new()).push_all(indices.as_slice()),
+                        None => {}
+                    }
+                }
+            }
+        } else {
+            if old_label == 0 {
+                return;
+            }
+
+            let connected_neighbors = neighbors.iter().filter(|&&neighbor| {
+                let (ref tile, _, _) = self.tiles[neighbor];
+                tile.regions[region_type] == old_label
+            }).count();
+
+            if connected_neighbors <= 1 {
+                //a dead end can be removed without splitting anything
+                let &(ref mut tile, _, _) = self.tiles.get_mut(index);
+                *tile.regions.get_mut(region_type) = 0;
+
+                let region_index = self.region_index.get_mut(region_type);
+                match region_index.find_mut(&old_label) {
+                    Some(indices) => indices.retain(|&tile_index| tile_index != index),
+                    None => {}
+                }
+                region_index.find_or_insert_with(0, |_| Vec::new()).push(index);
+            } else {
+                self.find_connected_regions(|tile| whitelisted(tile), region_type);
+            }
+        }
+    }
+
+    pub fn region_bounds(&self, region_type: uint, label: uint) -> Option<(Vector2i, Vector2i)> {
+        //label 0 is used for tiles outside of any region
+        if label == 0 {
+            return None;
+        }
+
+        let mut bounds: Option<(Vector2i, Vector2i)> = None;
+
+        for y in range(0, self.height) {
+            for x in range(0, self.width) {
+                let (ref tile, _, _) = self.tiles[y * self.width + x];
+                if tile.regions[region_type] != label {
+                    continue;
+                }
+
+                let (x, y) = (x as i32, y as i32);
+                bounds = match bounds {
+                    Some((start, end)) => Some((
+                        Vector2i::new(min(start.x, x), min(start.y, y)),
+                        Vector2i::new(max(end.x, x), max(end.y, y))
+                    )),
+                    None => Some((Vector2i::new(x, y), Vector2i::new(x, y)))
+                };
+            }
+        }
+
+        bounds
+    }
+
+    pub fn region_summary(&self, region_type: uint, label: uint) -> Option<RegionSummary> {
+        if label == 0 {
+            return None;
+        }
+
+        let mut summary = RegionSummary {
+            tiles: 0,
+            roads: 0,
+            residents: 0.0,
+            homes: 0,
+            employees: 0.0,
+            jobs: 0,
+            stored_goods: 0,
+            buffered_goods: 0,
+            connected_to_edge: false
+        };
+
+        for &(ref tile, _, _) in self.tiles.iter() {
+            if tile.regions[region_type] != label {
+                continue;
+            }
+
+            summary.tiles += 1;
+            match tile.tile_type {
+                tile::Road => summary.roads += 1,
+                tile::Residential {population, max_pop_per_level, ..} => {
+                    summary.residents += population;
+                    summary.homes += max_pop_per_level * (tile.variant + 1);
+                },
+                tile::Commercial {population, max_pop_per_level, goods_buffer, ..} => {
+                    summary.employees += population;
+                    summary.jobs += max_pop_per_level * (tile.variant + 1);
+                    summary.buffered_goods += goods_buffer;
+                },
+                tile::Industrial {population, max_pop_per_level, stored_goods, ..} => {
+                    summary.employees += population;
+                    summary.jobs += max_pop_per_level * (tile.variant + 1);
+                    summary.stored_goods += stored_goods;
+                },
+                _ => {}
+            }
+        }
+
+        let on_edge = self.regions_on_edge(region_type);
+        summary.connected_to_edge = label < on_edge.len() && on_edge[label];
+
+        Some(summary)
+    }
+
+    pub fn regions_on_edge(&self, region_type: uint) -> Vec<bool> {
+        let mut on_edge = Vec::from_elem(self.num_regions[region_type], false);
+
+        for y in range(0, self.height) {
+            for x in range(0, self.width) {
+                if x > 0 && x < self.width - 1 && y > 0 && y < self.height - 1 {
+                    continue;
+                }
+
+                let (ref tile, _, _) = self.tiles[y * self.width + x];
+                let region = tile.regions[region_type];
+                match tile.tile_type {
+                    tile::Road if region != 0 && region < on_edge.len() => *on_edge.get_mut(region) = true,
+                    _ => {}
+                }
+            }
+        }
+
+        on_edge
     }
 
     pub fn clear_selected(&mut self) {
+        self.tiles_changed = true;
         for &(_, _, ref mut selection) in self.tiles.mut_iter() {
             *selection = Deselected;
         }
@@ -332,22 +1148,92 @@ impl Map {
     }
 
     pub fn mut_tile(&mut self, index: uint) -> &mut (Tile, uint, Selection) {
+        self.tiles_changed = true;
         self.tiles.get_mut(index)
     }
 
-    pub fn tile_at(&mut self, pos: &Vector2i) -> Option<&(Tile, uint, Selection)> {
+    //exchanges the tiles at a and b, while the resources and selection stay on the ground
+    pub fn swap_tiles(&mut self, a: uint, b: uint) -> Result<(), String> {
+        self.tiles_changed = true;
+        if a >= self.tiles.len() || b >= self.tiles.len() {
+            return Err("Outside of the map".to_string());
+        }
+
+        if self.is_part_of_building(a) || self.is_part_of_building(b) {
+            return Err("Buildings larger than one tile can't be moved".to_string());
+        }
+
+        if a == b {
+            return Ok(());
+        }
+
+        let mut tile_a = {
+            let (ref tile, _, _) = self.tiles[a];
+            tile.clone()
+        };
+        let mut tile_b = {
+            let (ref tile, _, _) = self.tiles[b];
+            tile.clone()
+        };
+
+        //the region labels belong to the places, until they are updated for the new tiles
+        swap(&mut tile_a.regions, &mut tile_b.regions);
+
+        {
+            let &(ref mut tile, _, _) = self.tiles.get_mut(a);
+            *tile = tile_b;
+        }
+        {
+            let &(ref mut tile, _, _) = self.tiles.get_mut(b);
+            *tile = tile_a;
+        }
+
+        Ok(())
+    }
+
+    //moves the tile at from onto the grass at to, and leaves that grass behind
+    pub fn move_tile(&mut self, from: uint, to: uint) -> Result<(), String> {
+        if to >= self.tiles.len() {
+            return Err("Outside of the map".to_string());
+        }
+
+        match self.tiles[to] {
+            (Tile {tile_type: tile::Grass, ..}, _, _) => self.swap_tiles(from, to),
+            _ => Err("The destination is occupied".to_string())
+        }
+    }
+
+    fn is_part_of_building(&self, index: uint) -> bool {
+        let (ref tile, _, _) = self.tiles[index];
+        tile.anchor.is_some() || tile.tile_type.footprint() != (1, 1)
+    }
+
+    fn index_of(&self, pos: &Vector2i) -> Option<uint> {
         if pos.x >= 0 && pos.x < self.width as i32 && pos.y >= 0 && pos.y < self.height as i32 {
-            Some(&self.tiles[pos.x as uint + pos.y as uint * self.width])
+            Some(pos.x as uint + pos.y as uint * self.width)
         } else {
             None
         }
     }
 
+    pub fn tile_at(&self, pos: &Vector2i) -> Option<&(Tile, uint, Selection)> {
+        self.index_of(pos).map(|index| &self.tiles[index])
+    }
+
+    pub fn tile_at_mut(&mut self, pos: &Vector2i) -> Option<&mut (Tile, uint, Selection)> {
+        self.tiles_changed = true;
+        match self.index_of(pos) {
+            Some(index) => Some(self.tiles.get_mut(index)),
+            None => None
+        }
+    }
+
     pub fn tiles(&mut self) -> MutItems<(Tile, uint, Selection)> {
+        self.tiles_changed = true;
         self.tiles.mut_iter()
     }
 
-    pub fn select(&mut self, start: Vector2i, end: Vector2i, blacklisted: |&TileType| -> bool) {
+    fn clamp_selection(&self, start: Vector2i, end: Vector2i) -> (Vector2i, Vector2i) {
         let mut start = start;
         let mut end = end;
 
@@ -364,32 +1250,497 @@ impl Map {
         end.x = min(max(end.x, 0), self.width as i32 - 1);
         end.y = min(max(end.y, 0), self.height as i32 - 1);
 
+        (start, end)
+    }
+
+    pub fn select(&mut self, start: Vector2i, end: Vector2i, blacklisted: |&TileType| -> bool) {
+        self.tiles_changed = true;
+        let (start, end) = self.clamp_selection(start, end);
 
         for y in range(start.y as uint, end.y as uint + 1) {
             for x in range(start.x as uint, end.x as uint + 1) {
-                let &(ref tile, _, ref mut selection) = self.tiles.get_mut(y * self.width + x);
+                let index = y * self.width + x;
+                let new_selection = {
+                    let (ref tile, _, _) = self.tiles[index];
+                    if blacklisted(&tile.tile_type) { Invalid } else { Selected }
+                };
+                self.set_selection(index, new_selection);
+            }
+        }
+
+        self.select_whole_buildings();
+    }
+
+    //changes the selection of a tile, without counting a tile that's already selected twice
+    fn set_selection(&mut self, index: uint, new_selection: Selection) {
+        let &(_, _, ref mut selection) = self.tiles.get_mut(index);
+
+        match (&*selection, &new_selection) {
+            (&Selected, &Selected) => {},
+            (&Selected, _) => self.num_selected -= 1,
+            (_, &Selected) => self.num_selected += 1,
+            _ => {}
+        }
+
+        *selection = new_selection;
+    }
+
+    //selects the connected tiles around start that aren't blacklisted, up to max_tiles of them
+    pub fn select_area(&mut self, start: Vector2i, max_tiles: uint, blacklisted: |&TileType| -> bool) {
+        self.tiles_changed = true;
+        let start = match self.index_of(&start) {
+            Some(index) => index,
+            None => return
+        };
+
+        let mut stack = vec![start];
+        while self.num_selected < max_tiles {
+            let index = match stack.pop() {
+                Some(index) => index,
+                None => break
+            };
+
+            {
+                let &(ref tile, _, ref mut selection) = self.tiles.get_mut(index);
+                match *selection {
+                    Deselected => {},
+                    _ => continue
+                }
+
                 if blacklisted(&tile.tile_type) {
                     *selection = Invalid;
-                } else {
-                    *selection = Selected;
-                    self.num_selected += 1;
+                    continue;
+                }
+
+                *selection = Selected;
+                self.num_selected += 1;
+            }
+
+            let pos = Vector2i::new((index % self.width) as i32, (index / self.width) as i32);
+            for &(dx, dy) in [(-1i32, 0i32), (0, 1), (1, 0), (0, -1)].iter() {
+                match self.index_of(&pos.add(&Vector2i::new(dx, dy))) {
+                    Some(neighbor) => stack.push(neighbor),
+                    None => {}
+                }
+            }
+        }
+
+        self.select_whole_buildings();
+    }
+
+    //selects the area a new building would cover, with pos as its anchor, or nothing if it doesn't fit
+    pub fn select_footprint(&mut self, pos: Vector2i, footprint: (uint, uint), blacklisted: |&TileType| -> bool) {
+        self.tiles_changed = true;
+        let (footprint_width, footprint_height) = footprint;
+        let mut fits = true;
+
+        for y in range(pos.y, pos.y + footprint_height as i32) {
+            for x in range(pos.x, pos.x + footprint_width as i32) {
+                match self.index_of(&Vector2i::new(x, y)) {
+                    Some(index) => {
+                        let &(ref tile, _, ref mut selection) = self.tiles.get_mut(index);
+                        if blacklisted(&tile.tile_type) {
+                            *selection = Invalid;
+                            fits = false;
+                        } else {
+                            *selection = Selected;
+                        }
+                    },
+                    None => fits = false
+                }
+            }
+        }
+
+        if fits {
+            self.num_selected = footprint_width * footprint_height;
+        } else {
+            for &(_, _, ref mut selection) in self.tiles.mut_iter() {
+                match *selection {
+                    Selected => *selection = Invalid,
+                    _ => {}
+                }
+            }
+            self.num_selected = 0;
+        }
+    }
+
+    //makes the selected anchor tile of a newly placed building the anchor of the rest of the selection
+    pub fn link_selected_footprint(&mut self) {
+        self.tiles_changed = true;
+        let width = self.width;
+        let mut anchor = None;
+
+        for (index, &(ref mut tile, _, ref selection)) in self.tiles.mut_iter().enumerate() {
+            match *selection {
+                Selected => match anchor {
+                    Some(_) => tile.anchor = anchor,
+                    None => {
+                        anchor = Some((index % width, index / width));
+                        tile.anchor = None;
+                    }
+                },
+                _ => {}
+            }
+        }
+    }
+
+    //a building is only touched as a whole, so selecting one of its tiles selects all of them
+    fn select_whole_buildings(&mut self) {
+        let mut anchors = Vec::new();
+
+        for (index, &(ref tile, _, ref selection)) in self.tiles.iter().enumerate() {
+            match *selection {
+                Selected if tile.tile_type.footprint() != (1, 1) => {
+                    let anchor = tile.anchor.unwrap_or((index % self.width, index / self.width));
+                    if !anchors.contains(&anchor) {
+                        anchors.push(anchor);
+                    }
+                },
+                _ => {}
+            }
+        }
+
+        for &(anchor_x, anchor_y) in anchors.iter() {
+            let (ref anchor_tile, _, _) = self.tiles[anchor_y * self.width + anchor_x];
+            let (footprint_width, footprint_height) = anchor_tile.tile_type.footprint();
+            for y in range(anchor_y, min(anchor_y + footprint_height, self.height)) {
+                for x in range(anchor_x, min(anchor_x + footprint_width, self.width)) {
+                    let &(ref tile, _, ref mut selection) = self.tiles.get_mut(y * self.width + x);
+                    let part_of_building = (x == anchor_x && y == anchor_y) || tile.anchor == Some((anchor_x, anchor_y));
+                    match *selection {
+                        Selected => {},
+                        _ if part_of_building => {
+                            *selection = Selected;
+                            self.num_selected += 1;
+                        },
+                        _ => {}
+                    }
+                }
+            }
+        }
+    }
+
+    fn footprint_complete(&self, anchor_x: uint, anchor_y: uint) -> bool {
+        let (ref anchor_tile, _, _) = self.tiles[anchor_y * self.width + anchor_x];
+        let (footprint_width, footprint_height) = anchor_tile.tile_type.footprint();
+        if anchor_x + footprint_width > self.width || anchor_y + footprint_height > self.height {
+            return false;
+        }
+
+        for y in range(anchor_y, anchor_y + footprint_height) {
+            for x in range(anchor_x, anchor_x + footprint_width) {
+                if x == anchor_x && y == anchor_y {
+                    continue;
+                }
+
+                let (ref tile, _, _) = self.tiles[y * self.width + x];
+                if tile.anchor != Some((anchor_x, anchor_y)) {
+                    return false;
+                }
+            }
+        }
+
+        true
+    }
+
+    //which tile to draw when reaching (x, y) and how many tiles it covers, if anything
+    fn drawn_at(&self, x: uint, y: uint) -> Option<(uint, uint, uint, uint)> {
+        let (ref tile, _, _) = self.tiles[y * self.width + x];
+        let (footprint_width, footprint_height) = tile.tile_type.footprint();
+        let (anchor_x, anchor_y) = tile.anchor.unwrap_or((x, y));
+
+        if footprint_width * footprint_height == 1 || !self.footprint_complete(anchor_x, anchor_y) {
+            return Some((x, y, 1, 1));
+        }
+
+        //buildings are drawn with their front tile, so that everything behind them is already drawn
+        if x == anchor_x + footprint_width - 1 && y == anchor_y + footprint_height - 1 {
+            Some((anchor_x, anchor_y, footprint_width, footprint_height))
+        } else {
+            None
+        }
+    }
+
+    pub fn preview_cost(&self, start: Vector2i, end: Vector2i, cost: f64, blacklisted: |&TileType| -> bool) -> f64 {
+        let (start, end) = self.clamp_selection(start, end);
+        let mut total = 0.0;
+
+        for y in range(start.y as uint, end.y as uint + 1) {
+            for x in range(start.x as uint, end.x as uint + 1) {
+                let (ref tile, _, _) = self.tiles[y * self.width + x];
+                if !blacklisted(&tile.tile_type) {
+                    total += tile.replacement_cost(cost) - tile.demolish_refund();
                 }
             }
         }
+
+        total
     }
 
-    pub fn selected(&mut self) -> FilterMap<&mut (Tile, uint, Selection), (&mut Tile, &mut uint), MutItems<(Tile, uint, Selection)>> {
-        self.tiles.mut_iter().filter_map(|&(ref mut tile, ref mut resources, selection)| match selection {
-            Selected => Some((tile, resources)),
+    pub fn select_line(&mut self, start: Vector2i, end: Vector2i, blacklisted: |&TileType| -> bool) {
+        self.tiles_changed = true;
+        let dx = (end.x - start.x).abs();
+        let dy = -(end.y - start.y).abs();
+        let step_x = if start.x < end.x { 1 } else { -1 };
+        let step_y = if start.y < end.y { 1 } else { -1 };
+        let mut error = dx + dy;
+        let mut pos = start;
+
+        loop {
+            match self.index_of(&pos) {
+                Some(index) => {
+                    let new_selection = {
+                        let (ref tile, _, _) = self.tiles[index];
+                        if blacklisted(&tile.tile_type) { Invalid } else { Selected }
+                    };
+                    self.set_selection(index, new_selection);
+                },
+                None => {}
+            }
+
+            if pos.x == end.x && pos.y == end.y {
+                self.select_whole_buildings();
+                break;
+            }
+
+            //only step along one axis at a time to keep the line four-connected
+            let double_error = 2 * error;
+            if double_error - dy > dx - double_error {
+                error += dy;
+                pos.x += step_x;
+            } else {
+                error += dx;
+                pos.y += step_y;
+            }
+        }
+    }
+
+    //selects a road route from start to end that goes around blacklisted tiles, but not the existing roads
+    //it passes through, and marks the ends as invalid if there is no route
+    pub fn select_route(&mut self, start: Vector2i, end: Vector2i, blacklisted: |&TileType| -> bool) -> bool {
+        self.tiles_changed = true;
+        let (start, end) = match (self.index_of(&start), self.index_of(&end)) {
+            (Some(start), Some(end)) => (start, end),
+            _ => return false
+        };
+
+        let passable: Vec<bool> = self.tiles.iter()
+            .map(|&(ref tile, _, _)| tile.tile_type.similar_to(&tile::Road) || !blacklisted(&tile.tile_type))
+            .collect();
+
+        match self.find_route(start, end, passable.as_slice()) {
+            Some(route) => {
+                for &index in route.iter() {
+                    let is_road = {
+                        let (ref tile, _, _) = self.tiles[index];
+                        tile.tile_type.similar_to(&tile::Road)
+                    };
+
+                    if !is_road {
+                        self.set_selection(index, Selected);
+                    }
+                }
+
+                true
+            },
+            None => {
+                self.set_selection(start, Invalid);
+                self.set_selection(end, Invalid);
+                false
+            }
+        }
+    }
+
+    //A* over the four-connected grid, where turning costs a bit extra
+    fn find_route(&self, start: uint, end: uint, passable: &[bool]) -> Option<Vec<uint>> {
+        if !passable[start] || !passable[end] {
+            return None;
+        }
+
+        let estimate = |index: uint| {
+            let distance = ((index % self.width) as int - (end % self.width) as int).abs() + ((index / self.width) as int - (end / self.width) as int).abs();
+            distance as uint * ROUTE_STEP_COST
+        };
+
+        let mut best_costs: Vec<Option<uint>> = Vec::from_elem(self.tiles.len() * 5, None);
+        let mut came_from: Vec<Option<uint>> = Vec::from_elem(self.tiles.len() * 5, None);
+        let mut queue = PriorityQueue::new();
+
+        *best_costs.get_mut(start * 5 + 4) = Some(0);
+        queue.push(RouteStep { cost: 0, estimate: estimate(start), state: start * 5 + 4 });
+
+        loop {
+            let step = match queue.pop() {
+                Some(step) => step,
+                None => return None
+            };
+
+            let (index, direction) = (step.state / 5, step.state % 5);
+
+            if index == end {
+                let mut route = vec![index];
+                let mut state = step.state;
+                loop {
+                    match came_from[state] {
+                        Some(previous) => {
+                            route.push(previous / 5);
+                            state = previous;
+                        },
+                        None => break
+                    }
+                }
+
+                route.reverse();
+                return Some(route);
+            }
+
+            //a cheaper way here was already explored
+            if best_costs[step.state].map_or(false, |best| step.cost > best) {
+                continue;
+            }
+
+            let pos = Vector2i::new((index % self.width) as i32, (index / self.width) as i32);
+            for (new_direction, &(dx, dy)) in [(-1i32, 0i32), (0, 1), (1, 0), (0, -1)].iter().enumerate() {
+                let neighbor = match self.index_of(&pos.add(&Vector2i::new(dx, dy))) {
+                    Some(neighbor) if passable[neighbor] => neighbor,
+                    _ => continue
+                };
+
+                let turn_cost = if direction != 4 && direction != new_direction { ROUTE_TURN_COST } else { 0 };
+                let cost = step.cost + ROUTE_STEP_COST + turn_cost;
+                let state = neighbor * 5 + new_direction;
+
+                if best_costs[state].map_or(true, |best| cost < best) {
+                    *best_costs.get_mut(state) = Some(cost);
+                    *came_from.get_mut(state) = Some(step.state);
+                    queue.push(RouteStep { cost: cost, estimate: cost + estimate(neighbor), state: state });
+                }
+            }
+        }
+    }
+
+    //yields the index, tile and resources of each selected tile
+    pub fn selected(&mut self) -> FilterMap<(uint, &mut (Tile, uint, Selection)), (uint, &mut Tile, &mut uint), Enumerate<MutItems<(Tile, uint, Selection)>>> {
+        self.tiles_changed = true;
+        self.tiles.mut_iter().enumerate().filter_map(|(index, &(ref mut tile, ref mut resources, selection))| match selection {
+            Selected => Some((index, tile, resources)),
             _ => None
         })
     }
 
-    pub fn shuffled(&mut self) -> ShuffledItems<(Tile, uint, Selection)> {
-        ShuffledItems::new(&mut self.tiles)
+    pub fn shuffled<R: Rng>(&mut self, rng: &mut R) -> ShuffledItems<(Tile, uint, Selection)> {
+        self.tiles_changed = true;
+        ShuffledItems::new(&mut self.tiles, rng)
+    }
+}
+
+//two octaves of value noise, scaled to fill the range from 0 to 1
+fn make_heightmap<R: Rng>(rng: &mut R, width: uint, height: uint) -> Vec<f32> {
+    let mut heights = Vec::from_elem(width * height, 0.0f32);
+
+    for &(cell_size, weight) in [(8u, 0.65f32), (4u, 0.35f32)].iter() {
+        let lattice_width = width / cell_size + 2;
+        let lattice_height = height / cell_size + 2;
+        let lattice: Vec<f32> = range(0, lattice_width * lattice_height).map(|_| rng.gen()).collect();
+
+        for y in range(0, height) {
+            for x in range(0, width) {
+                let (cell_x, cell_y) = (x / cell_size, y / cell_size);
+                let fx = smoothstep((x % cell_size) as f32 / cell_size as f32);
+                let fy = smoothstep((y % cell_size) as f32 / cell_size as f32);
+
+                let corner = |dx: uint, dy: uint| lattice[(cell_y + dy) * lattice_width + cell_x + dx];
+                let top = corner(0, 0) + (corner(1, 0) - corner(0, 0)) * fx;
+                let bottom = corner(0, 1) + (corner(1, 1) - corner(0, 1)) * fx;
+
+                *heights.get_mut(y * width + x) += (top + (bottom - top) * fy) * weight;
+            }
+        }
+    }
+
+    let lowest = heights.iter().fold(1.0f32, |lowest, &height| lowest.min(height));
+    let highest = heights.iter().fold(0.0f32, |highest, &height| highest.max(height));
+    if highest > lowest {
+        for height in heights.mut_iter() {
+            *height = (*height - lowest) / (highest - lowest);
+        }
+    }
+
+    heights
+}
+
+//multiplies the channels, the way sprite colors are applied to the texture
+fn modulate(a: &Color, b: &Color) -> Color {
+    Color::new_RGBA(
+        (a.red as uint * b.red as uint / 255) as u8,
+        (a.green as uint * b.green as uint / 255) as u8,
+        (a.blue as uint * b.blue as uint / 255) as u8,
+        (a.alpha as uint * b.alpha as uint / 255) as u8
+    )
+}
+
+fn smoothstep(t: f32) -> f32 {
+    t * t * (3.0 - 2.0 * t)
+}
+
+//a random walk from the top edge to the bottom edge, which is mostly heading down but meanders sideways
+fn carve_river<R: Rng>(rng: &mut R, width: uint, height: uint) -> Vec<bool> {
+    let mut water = Vec::from_elem(width * height, false);
+    if width == 0 || height == 0 {
+        return water;
+    }
+
+    let river_width = rng.gen_range(1u, 4);
+    let mut x = rng.gen_range(0, width) as int;
+    let mut y = 0u;
+
+    while y < height {
+        for offset in range(0, river_width as int) {
+            let river_x = x + offset;
+            if river_x >= 0 && river_x < width as int {
+                *water.get_mut(y * width + river_x as uint) = true;
+            }
+        }
+
+        if rng.gen_weighted_bool(3) {
+            x = if rng.gen() { x + 1 } else { x - 1 };
+            x = max(0, min(x, width as int - 1));
+        } else {
+            y += 1;
+        }
+    }
+
+    water
+}
+
+fn write_header<W: Writer>(file: &mut W) -> io::IoResult<()> {
+    try!(file.write_str(SAVE_MAGIC));
+    file.write_be_u16(SAVE_VERSION)
+}
+
+fn invalid_map(reason: String) -> io::IoError {
+    io::IoError {
+        kind: io::OtherIoError,
+        desc: "invalid map data",
+        detail: Some(reason)
+    }
+}
+
+fn unsupported_version(version: u16) -> io::IoError {
+    io::IoError {
+        kind: io::OtherIoError,
+        desc: "unsupported map version",
+        detail: Some(format!("found version {}, but only versions up to {} are supported", version, SAVE_VERSION))
     }
 }
 
+//the saved form of a tile, which is what decides if it has changed
+fn tile_data(tile: &Tile) -> io::IoResult<Vec<u8>> {
+    let mut data = io::MemWriter::new();
+    try!(tile.write_to(&mut data));
+    Ok(data.unwrap())
+}
+
 struct ShuffledItems<'a, T: 'a> {
     items: &'a mut Vec<T>,
     indices: Vec<uint>,
@@ -397,9 +1748,9 @@ struct ShuffledItems<'a, T: 'a> {
 }
 
 impl<'a, T: 'a> ShuffledItems<'a, T> {
-    pub fn new(items: &'a mut Vec<T>) -> ShuffledItems<'a, T> {
+    pub fn new<R: Rng>(items: &'a mut Vec<T>, rng: &mut R) -> ShuffledItems<'a, T> {
         let mut indices: Vec<uint> = range(0, items.len()).collect();
-        task_rng().shuffle(indices.as_mut_slice());
+        rng.shuffle(indices.as_mut_slice());
         ShuffledItems {
             items: items,
             indices: indices,
@@ -412,17 +1763,458 @@ impl<'a, T: 'a> ShuffledItems<'a, T> {
     }
 }
 
-impl<'a, T: 'a> iter::Iterator<&'a mut T> for ShuffledItems<'a, T> {
-    fn next(&mut self) -> Option<&'a mut T> {
+//yields the original index of each item together with the item
+impl<'a, T: 'a> iter::Iterator<(uint, &'a mut T)> for ShuffledItems<'a, T> {
+    fn next(&mut self) -> Option<(uint, &'a mut T)> {
         if self.counter < self.items.len() {
             let index = self.indices[self.counter];
             self.counter += 1;
             unsafe {
                 //less nice...
-                Some(transmute(self.items.get_mut(index)))
+                Some((index, transmute(self.items.get_mut(index))))
             }
         } else {
             None
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+    use std::collections::HashMap;
+
+    use rsfml::system::vector2::{Vector2f, Vector2i};
+
+    use tile;
+    use tile::{Tile, TileType};
+    use super::{Map, GenerationParams, Selected, Invalid, tile_data};
+
+    fn is_road(tile_type: &TileType) -> bool {
+        tile_type.similar_to(&tile::Road)
+    }
+
+    //replaces the tile like City::bulldoze does, keeping its region labels until the regions are updated
+    fn place(map: &mut Map, tile_atlas: &HashMap<&'static str, Tile>, x: uint, y: uint, name: &'static str) {
+        let index = y * map.width + x;
+        let &(ref mut tile, _, _) = map.mut_tile(index);
+        let regions = tile.regions.clone();
+        *tile = tile_atlas.find(&name).expect("unknown tile").clone();
+        tile.regions = regions;
+    }
+
+    //a flat map where '#' is road and '~' is water, with the road regions found from scratch
+    fn road_map(tile_atlas: &HashMap<&'static str, Tile>, rows: &[&'static str]) -> Map {
+        let mut params = GenerationParams::new();
+        params.width = rows[0].len();
+        params.height = rows.len();
+        params.flat = true;
+
+        let mut map = Map::empty(8);
+        map.regenerate([1, 2, 3, 4], &params, tile_atlas);
+
+        for (y, row) in rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                match c {
+                    '#' => place(&mut map, tile_atlas, x, y, "road"),
+                    '~' => place(&mut map, tile_atlas, x, y, "water"),
+                    _ => {}
+                }
+            }
+        }
+
+        map.find_connected_regions(|tile| is_road(tile), 0);
+        map
+    }
+
+    fn is_water(tile_type: &TileType) -> bool {
+        *tile_type == tile::Water
+    }
+
+    //the selection drawn like the rows of road_map, where '*' is selected and '!' is invalid
+    fn assert_selection(map: &Map, rows: &[&'static str]) {
+        for (y, row) in rows.iter().enumerate() {
+            let selection: String = range(0, map.width).map(|x| match map.tiles[y * map.width + x] {
+                (_, _, Selected) => '*',
+                (_, _, Invalid) => '!',
+                _ => '.'
+            }).collect();
+
+            assert_eq!(selection.as_slice(), *row);
+        }
+    }
+
+    //how many times a route changes direction
+    fn count_turns(route: &[uint]) -> uint {
+        let steps: Vec<int> = route.windows(2).map(|pair| pair[1] as int - pair[0] as int).collect();
+        steps.windows(2).filter(|pair| pair[0] != pair[1]).count()
+    }
+
+    fn label(map: &Map, x: uint, y: uint) -> uint {
+        let (ref tile, _, _) = map.tiles[y * map.width + x];
+        tile.regions[0]
+    }
+
+    //the labels may differ, but the same tiles have to be grouped together
+    fn assert_same_regions(incremental: &Map, full: &Map) {
+        let mut incremental_to_full = HashMap::new();
+        let mut full_to_incremental = HashMap::new();
+
+        for index in range(0, incremental.tiles.len()) {
+            let (ref incremental_tile, _, _) = incremental.tiles[index];
+            let (ref full_tile, _, _) = full.tiles[index];
+            let (incremental_label, full_label) = (incremental_tile.regions[0], full_tile.regions[0]);
+
+            assert!((incremental_label == 0) == (full_label == 0),
+                "tile {} has label {} incrementally and {} from scratch", index, incremental_label, full_label);
+
+            if incremental_label != 0 {
+                assert_eq!(*incremental_to_full.find_or_insert(incremental_label, full_label), full_label);
+                assert_eq!(*full_to_incremental.find_or_insert(full_label, incremental_label), incremental_label);
+            }
+        }
+    }
+
+    //every tile has to be listed under its own label, and nowhere else
+    fn assert_index_matches_labels(map: &Map) {
+        for region in range(0, map.num_regions[0]) {
+            let mut listed: Vec<uint> = map.region_tiles(0, region).map(|&index| index).collect();
+            listed.sort();
+
+            let labeled: Vec<uint> = range(0, map.tiles.len()).filter(|&index| {
+                let (ref tile, _, _) = map.tiles[index];
+                tile.regions[0] == region
+            }).collect();
+
+            assert_eq!(listed, labeled);
+        }
+    }
+
+    //changes one tile and compares the incremental update with a full one
+    fn check_change(rows: &[&'static str], x: uint, y: uint, name: &'static str) -> Map {
+        check_change_with_wrapping(rows, x, y, name, false)
+    }
+
+    fn check_change_with_wrapping(rows: &[&'static str], x: uint, y: uint, name: &'static str, wrap_edges: bool) -> Map {
+        let tile_atlas = tile::test_atlas(8);
+        let mut incremental = road_map(&tile_atlas, rows);
+        incremental.wrap_edges = wrap_edges;
+        incremental.find_connected_regions(|tile| is_road(tile), 0);
+        place(&mut incremental, &tile_atlas, x, y, name);
+
+        let mut full = incremental.clone();
+        incremental.update_regions_incremental(&Vector2i::new(x as i32, y as i32), |tile| is_road(tile), 0);
+        full.find_connected_regions(|tile| is_road(tile), 0);
+
+        assert_same_regions(&incremental, &full);
+        assert_index_matches_labels(&incremental);
+        incremental
+    }
+
+    #[test]
+    fn adding_extends_a_region() {
+        let map = check_change([".##..", ".....", "....."], 3, 0, "road");
+        assert_eq!(label(&map, 1, 0), label(&map, 3, 0));
+    }
+
+    #[test]
+    fn adding_alone_makes_a_new_region() {
+        let map = check_change(["##...", ".....", "....."], 3, 2, "road");
+        assert!(label(&map, 3, 2) != 0);
+        assert!(label(&map, 3, 2) != label(&map, 0, 0));
+    }
+
+    #[test]
+    fn adding_merges_regions() {
+        let map = check_change(["##.##", "....#", "#...#"], 2, 0, "road");
+        assert_eq!(label(&map, 0, 0), label(&map, 4, 2));
+        assert!(label(&map, 0, 2) != label(&map, 0, 0));
+    }
+
+    #[test]
+    fn removing_a_dead_end_keeps_the_region() {
+        let map = check_change(["####.", "...#.", "....."], 3, 1, "grass");
+        assert_eq!(label(&map, 3, 1), 0);
+        assert_eq!(label(&map, 0, 0), label(&map, 3, 0));
+    }
+
+    #[test]
+    fn removing_a_middle_tile_splits_the_region() {
+        let map = check_change([".....", "#####", "....."], 2, 1, "grass");
+        assert_eq!(label(&map, 2, 1), 0);
+        assert!(label(&map, 1, 1) != label(&map, 3, 1));
+    }
+
+    #[test]
+    fn removing_from_a_loop_keeps_it_together() {
+        let map = check_change(["###..", "#.#..", "###.."], 1, 0, "grass");
+        assert_eq!(label(&map, 0, 0), label(&map, 2, 0));
+    }
+
+    #[test]
+    fn picking_follows_the_tile_size() {
+        for &tile_size in [16u, 32].iter() {
+            let tile_atlas = tile::test_atlas(tile_size);
+
+            let mut params = GenerationParams::new();
+            params.width = 12;
+            params.height = 7;
+            params.flat = true;
+
+            let mut map = Map::empty(tile_size);
+            map.regenerate([1, 2, 3, 4], &params, &tile_atlas);
+
+            let half_width = tile_size as f32;
+            let half_height = tile_size as f32 * 0.5;
+
+            for index in range(0, map.tiles.len()) {
+                let expected = ((index % map.width) as i32, (index / map.width) as i32);
+                let center = map.tile_center(index);
+
+                //the center and points near the left, right, top and bottom corners of the diamond
+                let points = [
+                    center,
+                    Vector2f::new(center.x - half_width * 0.9, center.y),
+                    Vector2f::new(center.x + half_width * 0.9, center.y),
+                    Vector2f::new(center.x, center.y - half_height * 0.9),
+                    Vector2f::new(center.x, center.y + half_height * 0.9)
+                ];
+
+                for point in points.iter() {
+                    let tile = map.screen_to_tile(point);
+                    assert_eq!((tile.x, tile.y), expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn selected_yields_the_selected_indices() {
+        let tile_atlas = tile::test_atlas(8);
+        let mut map = road_map(&tile_atlas, ["......", "......", "......", "......", "......"]);
+
+        //the rectangle from (1, 1) to (3, 2), on a map that is 6 tiles wide
+        map.select(Vector2i::new(1, 1), Vector2i::new(3, 2), |_| false);
+
+        let indices: Vec<uint> = map.selected().map(|(index, _, _)| index).collect();
+        assert_eq!(indices, vec![7, 8, 9, 13, 14, 15]);
+        assert_eq!(map.num_selected, 6);
+
+        //the yielded tiles are the ones at the indices
+        for (index, tile, _) in map.selected() {
+            tile.regions = vec![index];
+        }
+
+        for &index in [7u, 8, 9, 13, 14, 15].iter() {
+            let (ref tile, _, _) = map.tiles[index];
+            assert_eq!(tile.regions, vec![index]);
+        }
+
+        map.clear_selected();
+        assert_eq!(map.selected().count(), 0);
+    }
+
+    #[test]
+    fn flat_maps_are_only_grass() {
+        let tile_atlas = tile::test_atlas(8);
+
+        //flat has to win over the other terrain options
+        let mut params = GenerationParams::new();
+        params.width = 30;
+        params.height = 20;
+        params.river = true;
+        params.elevation = true;
+        params.forest_chance = 1.0;
+        params.flat = true;
+
+        let mut map = Map::empty(8);
+        map.regenerate([1, 2, 3, 4], &params, &tile_atlas);
+
+        let counts = map.count_tiles();
+        assert_eq!(counts.grass, 30 * 20);
+        assert_eq!(counts.forest + counts.water + counts.void, 0);
+    }
+
+    #[test]
+    fn airports_need_one_tile_next_to_a_road() {
+        let tile_atlas = tile::test_atlas(8);
+        let mut map = road_map(&tile_atlas, ["......", "......", "......", "######"]);
+        let airport = tile_atlas.find(&"airport").unwrap();
+        let required = airport.tile_type.required_neighbor().expect("airports have no requirement");
+
+        //only the bottom row of the footprint touches the road
+        map.select_footprint(Vector2i::new(1, 1), airport.tile_type.footprint(), |_| false);
+        assert!(map.selection_adjacent_to(&required));
+
+        map.clear_selected();
+        map.select_footprint(Vector2i::new(1, 0), airport.tile_type.footprint(), |_| false);
+        assert!(!map.selection_adjacent_to(&required));
+    }
+
+    fn count_selected(map: &Map) -> uint {
+        map.tiles.iter().filter(|&&(_, _, selection)| match selection {
+            super::Selected => true,
+            _ => false
+        }).count()
+    }
+
+    #[test]
+    fn num_selected_follows_the_selection() {
+        let tile_atlas = tile::test_atlas(8);
+        let mut map = road_map(&tile_atlas, ["......", "..##..", "......", "......", "......"]);
+
+        //overlapping rectangles don't count the shared tiles twice
+        map.select(Vector2i::new(0, 0), Vector2i::new(2, 2), |_| false);
+        map.select(Vector2i::new(1, 1), Vector2i::new(4, 3), |_| false);
+        assert_eq!(map.num_selected, count_selected(&map));
+
+        //blacklisted tiles are deselected
+        map.select(Vector2i::new(0, 1), Vector2i::new(5, 1), |tile| is_road(tile));
+        assert_eq!(map.num_selected, count_selected(&map));
+
+        map.select_line(Vector2i::new(0, 4), Vector2i::new(5, 0), |_| false);
+        assert_eq!(map.num_selected, count_selected(&map));
+
+        map.clear_selected();
+        map.select_area(Vector2i::new(0, 4), 7, |_| false);
+        assert_eq!(map.num_selected, 7);
+        assert_eq!(count_selected(&map), 7);
+
+        map.clear_selected();
+        let airport = tile_atlas.find(&"airport").unwrap();
+        map.select_footprint(Vector2i::new(4, 3), airport.tile_type.footprint(), |_| false);
+        assert_eq!(map.num_selected, count_selected(&map));
+
+        //the footprint doesn't fit past the edge
+        map.clear_selected();
+        map.select_footprint(Vector2i::new(5, 4), airport.tile_type.footprint(), |_| false);
+        assert_eq!(map.num_selected, 0);
+        assert_eq!(count_selected(&map), 0);
+    }
+
+    #[test]
+    fn adding_merges_regions_across_wrapped_edges() {
+        let map = check_change_with_wrapping(["##...", ".....", "...##"], 4, 0, "road", true);
+        assert_eq!(label(&map, 0, 0), label(&map, 4, 0));
+        assert_eq!(label(&map, 4, 0), label(&map, 4, 2));
+    }
+
+    #[test]
+    fn removing_a_dead_end_across_wrapped_edges() {
+        //the last tile only touches the rest of the row over the edge
+        let map = check_change_with_wrapping([".....", "###.#", "....."], 4, 1, "grass", true);
+        assert_eq!(label(&map, 4, 1), 0);
+        assert_eq!(label(&map, 0, 1), label(&map, 2, 1));
+    }
+
+    #[test]
+    fn removing_from_a_wrapped_ring_keeps_it_together() {
+        let map = check_change_with_wrapping(["#####", ".....", "....."], 2, 0, "grass", true);
+        assert_eq!(label(&map, 1, 0), label(&map, 3, 0));
+    }
+
+    #[test]
+    fn removing_a_wrapped_middle_tile_splits_the_region() {
+        let map = check_change_with_wrapping(["#....", "#....", "#....", "....."], 0, 1, "grass", true);
+        assert!(label(&map, 0, 0) != label(&map, 0, 2));
+    }
+
+    #[test]
+    fn roads_connect_across_wrapped_edges() {
+        let tile_atlas = tile::test_atlas(8);
+
+        //a loop that only closes by going over the left, right, top and bottom edges
+        let rows = ["#...#", ".....", ".....", "#...#"];
+
+        let mut map = road_map(&tile_atlas, rows);
+        assert!(label(&map, 0, 0) != label(&map, 4, 0));
+
+        map.wrap_edges = true;
+        map.find_connected_regions(|tile| is_road(tile), 0);
+
+        let region = label(&map, 0, 0);
+        assert!(region != 0);
+        assert_eq!(label(&map, 4, 0), region);
+        assert_eq!(label(&map, 0, 3), region);
+        assert_eq!(label(&map, 4, 3), region);
+        assert_eq!(map.num_regions[0], 2);
+        assert_index_matches_labels(&map);
+    }
+
+    #[test]
+    fn routes_go_around_water() {
+        let tile_atlas = tile::test_atlas(8);
+        let mut map = road_map(&tile_atlas, [".......", "...~...", "...~...", "...~..."]);
+
+        assert!(map.select_route(Vector2i::new(1, 2), Vector2i::new(5, 2), |tile| is_water(tile)));
+        assert_selection(&map, [".*****.", ".*.~.*.", ".*.~.*.", "......."]);
+    }
+
+    #[test]
+    fn routes_pass_through_roads_without_selecting_them() {
+        let tile_atlas = tile::test_atlas(8);
+        let mut map = road_map(&tile_atlas, [".......", "..###..", "......."]);
+
+        //the existing roads are passable even if roads are blacklisted for building
+        assert!(map.select_route(Vector2i::new(0, 1), Vector2i::new(6, 1), |tile| is_road(tile)));
+        assert_selection(&map, [".......", "**...**", "......."]);
+    }
+
+    #[test]
+    fn missing_routes_mark_the_ends_invalid() {
+        let tile_atlas = tile::test_atlas(8);
+        let mut map = road_map(&tile_atlas, ["..~..", "..~..", "..~.."]);
+
+        assert!(!map.select_route(Vector2i::new(0, 1), Vector2i::new(4, 1), |tile| is_water(tile)));
+        assert_selection(&map, [".....", "!...!", "....."]);
+    }
+
+    #[test]
+    fn routes_prefer_straight_lines() {
+        let tile_atlas = tile::test_atlas(8);
+        let map = road_map(&tile_atlas, [".....", ".....", ".....", "....."]);
+        let passable = Vec::from_elem(map.tiles.len(), true);
+
+        //every shortest route is as long, but only two of them turn just once
+        let route = map.find_route(0, 2 * map.width + 3, passable.as_slice()).unwrap();
+        assert_eq!(route.len(), 6);
+        assert_eq!((route[0], route[5]), (0, 2 * map.width + 3));
+        assert_eq!(count_turns(route.as_slice()), 1);
+    }
+
+    #[test]
+    fn deltas_restore_the_changed_tiles() {
+        let tile_atlas = tile::test_atlas(8);
+        let baseline = road_map(&tile_atlas, ["....", "....", "...."]);
+
+        let mut map = baseline.clone();
+        place(&mut map, &tile_atlas, 1, 1, "road");
+        place(&mut map, &tile_atlas, 3, 2, "forest");
+
+        let mut data = io::MemWriter::new();
+        map.write_delta_to(&mut data, &baseline).unwrap();
+
+        let mut reader = io::MemReader::new(data.unwrap());
+        let loaded = baseline.delta_applied_from(&mut reader, &tile_atlas).unwrap();
+
+        for index in range(0, map.tiles.len()) {
+            let (ref expected, _, _) = map.tiles[index];
+            let (ref tile, _, _) = loaded.tiles[index];
+            assert!(tile_data(tile).unwrap() == tile_data(expected).unwrap(), "tile {} differs", index);
+        }
+    }
+
+    #[test]
+    fn deltas_need_a_baseline_of_the_same_size() {
+        let tile_atlas = tile::test_atlas(8);
+        let small = road_map(&tile_atlas, ["...", "..."]);
+        let large = road_map(&tile_atlas, ["....", "...."]);
+
+        let mut data = io::MemWriter::new();
+        assert!(large.write_delta_to(&mut data, &small).is_err());
+
+        large.write_delta_to(&mut data, &large).unwrap();
+        let mut reader = io::MemReader::new(data.unwrap());
+        assert!(small.delta_applied_from(&mut reader, &tile_atlas).is_err());
+    }
+}
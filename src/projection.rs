@@ -0,0 +1,78 @@
+use rsfml::system::vector2::{Vector2f, Vector2i};
+
+//the position of the top left corner of a tile's sprite
+pub fn tile_to_screen(x: uint, y: uint, tile_size: uint, map_width: uint) -> Vector2f {
+    let tile_size = tile_size as f32;
+    Vector2f::new(
+        (x as f32 - y as f32 + map_width as f32) * tile_size,
+        (x + y) as f32 * tile_size * 0.5
+    )
+}
+
+//the inverse of tile_to_screen, where a whole tile's diamond maps to the same tile
+pub fn screen_to_tile(pos: &Vector2f, tile_size: uint, map_width: uint) -> Vector2i {
+    let tile_size = tile_size as f32;
+    let map_width = map_width as f32;
+    Vector2i::new(
+        (pos.y / tile_size + pos.x / (2.0 * tile_size) - map_width * 0.5 - 0.5).floor() as i32,
+        (pos.y / tile_size - pos.x / (2.0 * tile_size) + map_width * 0.5 + 0.5).floor() as i32
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use rsfml::system::vector2::Vector2f;
+    use super::{tile_to_screen, screen_to_tile};
+
+    static TILE_SIZES: [uint, ..4] = [8, 16, 32, 64];
+    static MAP_WIDTH: uint = 20;
+
+    //tile_to_screen gives the corner of the sprite, which is outside the diamond, so the
+    //points are placed relative to the center of the diamond, as a fraction of its half width and height
+    fn point_in_tile(x: uint, y: uint, tile_size: uint, dx: f32, dy: f32) -> Vector2f {
+        let corner = tile_to_screen(x, y, tile_size, MAP_WIDTH);
+        let tile_size = tile_size as f32;
+        Vector2f::new(corner.x + tile_size * (1.0 + dx), corner.y + tile_size * 0.5 * (1.0 + dy))
+    }
+
+    #[test]
+    fn tile_centers_map_back_to_their_tiles() {
+        for &tile_size in TILE_SIZES.iter() {
+            for y in range(0u, MAP_WIDTH) {
+                for x in range(0u, MAP_WIDTH) {
+                    let tile = screen_to_tile(&point_in_tile(x, y, tile_size, 0.0, 0.0), tile_size, MAP_WIDTH);
+                    assert_eq!((tile.x, tile.y), (x as i32, y as i32));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn the_whole_diamond_maps_to_the_same_tile() {
+        let offsets = [(0.9f32, 0.0f32), (-0.9, 0.0), (0.0, 0.9), (0.0, -0.9), (0.4, 0.4), (-0.4, -0.4)];
+
+        for &tile_size in TILE_SIZES.iter() {
+            for y in range(0u, MAP_WIDTH) {
+                for x in range(0u, MAP_WIDTH) {
+                    for &(dx, dy) in offsets.iter() {
+                        let tile = screen_to_tile(&point_in_tile(x, y, tile_size, dx, dy), tile_size, MAP_WIDTH);
+                        assert_eq!((tile.x, tile.y), (x as i32, y as i32));
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn neighbors_are_one_step_apart() {
+        for &tile_size in TILE_SIZES.iter() {
+            let origin = tile_to_screen(5, 5, tile_size, MAP_WIDTH);
+            let right = tile_to_screen(6, 5, tile_size, MAP_WIDTH);
+            let down = tile_to_screen(5, 6, tile_size, MAP_WIDTH);
+            let size = tile_size as f32;
+
+            assert_eq!((right.x - origin.x, right.y - origin.y), (size, size * 0.5));
+            assert_eq!((down.x - origin.x, down.y - origin.y), (-size, size * 0.5));
+        }
+    }
+}
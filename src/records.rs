@@ -0,0 +1,51 @@
+use std::io;
+
+pub static RECORDS_PATH: &'static str = "media/records.dat";
+
+pub struct Records {
+    pub population: f64,
+    pub funds: f64
+}
+
+impl Records {
+    pub fn new() -> Records {
+        Records {
+            population: 0.0,
+            funds: 0.0
+        }
+    }
+
+    pub fn load(path: &Path) -> io::IoResult<Records> {
+        let mut file = try!(io::File::open(path));
+
+        Ok(Records {
+            population: try!(file.read_be_f64()),
+            funds: try!(file.read_be_f64())
+        })
+    }
+
+    pub fn save(&self, path: &Path) -> io::IoResult<()> {
+        let mut file = try!(io::File::create(path));
+
+        try!(file.write_be_f64(self.population));
+        try!(file.write_be_f64(self.funds));
+
+        Ok(())
+    }
+
+    pub fn update(&mut self, population: f64, funds: f64) -> bool {
+        let mut improved = false;
+
+        if population > self.population {
+            self.population = population;
+            improved = true;
+        }
+
+        if funds > self.funds {
+            self.funds = funds;
+            improved = true;
+        }
+
+        improved
+    }
+}
@@ -0,0 +1,236 @@
+use std::io;
+use std::io::fs;
+use std::collections::HashMap;
+
+use map::Map;
+use tile::Tile;
+use city::City;
+
+pub static SAVE_DIRECTORY: &'static str = "saves";
+
+pub struct SaveManager {
+    directory: Path,
+    baselines: HashMap<uint, Map>
+}
+
+impl SaveManager {
+    pub fn new(directory: Path) -> SaveManager {
+        SaveManager {
+            directory: directory,
+            baselines: HashMap::new()
+        }
+    }
+
+    pub fn slot_path(&self, slot: uint) -> Path {
+        self.directory.join(format!("city{}.sav", slot))
+    }
+
+    pub fn autosave_path(&self, slot: uint) -> Path {
+        self.directory.join(format!("autosave{}.dat", slot))
+    }
+
+    pub fn autosave_delta_path(&self, slot: uint) -> Path {
+        self.directory.join(format!("autosave{}.delta", slot))
+    }
+
+    //the first autosave to a slot is a full save, and the following ones only store what changed since then
+    pub fn autosave(&mut self, city: &City, slot: uint) -> Result<Path, String> {
+        try!(self.ensure_directory());
+
+        let path = self.autosave_path(slot);
+        let delta_path = self.autosave_delta_path(slot);
+
+        let delta_saved = match self.baselines.find(&slot) {
+            Some(baseline) => city.save_delta(&delta_path, &path, baseline).is_ok(),
+            None => false
+        };
+
+        if delta_saved {
+            return Ok(delta_path);
+        }
+
+        try!(city.save(&path).map_err(|e| describe_error(&e)));
+        self.baselines.insert(slot, city.map.clone());
+
+        //an old delta would be applied to the wrong baseline
+        if delta_path.exists() {
+            try!(fs::unlink(&delta_path).map_err(|e| describe_error(&e)));
+        }
+
+        Ok(path)
+    }
+
+    pub fn load_autosave(&self, city: &mut City, slot: uint, tile_atlas: &HashMap<&'static str, Tile>) -> Result<Path, String> {
+        let path = self.autosave_path(slot);
+        try!(self.load_path(city, &path, tile_atlas));
+        Ok(path)
+    }
+
+    fn ensure_directory(&self) -> Result<(), String> {
+        if !self.directory.is_dir() {
+            try!(fs::mkdir_recursive(&self.directory, io::UserRWX).map_err(|e| describe_error(&e)));
+        }
+
+        Ok(())
+    }
+
+    pub fn list_saves(&self) -> Vec<Path> {
+        match fs::readdir(&self.directory) {
+            Ok(mut paths) => {
+                //an autosave is listed once, by its full save, and its delta is applied when it's loaded
+                paths.retain(|path| path.extension_str() == Some("sav") || path.extension_str() == Some("dat"));
+                paths.sort_by(|a, b| a.as_vec().cmp(&b.as_vec()));
+                paths
+            },
+            Err(_) => Vec::new()
+        }
+    }
+
+    pub fn save(&self, city: &City, slot: uint) -> Result<Path, String> {
+        try!(self.ensure_directory());
+
+        let path = self.slot_path(slot);
+        try!(city.save(&path).map_err(|e| describe_error(&e)));
+        Ok(path)
+    }
+
+    pub fn load(&self, city: &mut City, slot: uint, tile_atlas: &HashMap<&'static str, Tile>) -> Result<Path, String> {
+        let path = self.slot_path(slot);
+        try!(self.load_path(city, &path, tile_atlas));
+        Ok(path)
+    }
+
+    //loads one of the saves from list_saves
+    pub fn load_path(&self, city: &mut City, path: &Path, tile_atlas: &HashMap<&'static str, Tile>) -> Result<(), String> {
+        let delta_path = path.with_extension("delta");
+        let loaded = if path.extension_str() == Some("dat") && delta_path.exists() {
+            city.load_delta(&delta_path, tile_atlas)
+        } else {
+            city.load(path, tile_atlas)
+        };
+
+        loaded.map_err(|e| describe_error(&e))
+    }
+}
+
+pub fn describe_error(error: &io::IoError) -> String {
+    let reason = match error.kind {
+        io::FileNotFound | io::PathDoesntExist => "the save does not exist".to_string(),
+        io::PermissionDenied => "permission denied".to_string(),
+        io::EndOfFile => "the save is incomplete".to_string(),
+        io::ShortWrite(..) => "the disk may be full".to_string(),
+        _ => error.desc.to_string()
+    };
+
+    match error.detail {
+        Some(ref detail) => format!("{} ({})", reason, detail),
+        None => reason
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::TempDir;
+    use std::collections::HashMap;
+
+    use map;
+    use tile;
+    use city::{City, CityParams};
+    use super::SaveManager;
+
+    fn flat_city(tile_atlas: &HashMap<&'static str, tile::Tile>) -> City {
+        let mut params = map::GenerationParams::new();
+        params.width = 8;
+        params.height = 8;
+        params.flat = true;
+
+        let mut map = map::Map::empty(8);
+        map.regenerate([1, 2, 3, 4], &params, tile_atlas);
+        City::with_params(map, CityParams::normal())
+    }
+
+    #[test]
+    fn saved_cities_are_listed_and_loaded() {
+        let directory = TempDir::new("saves").unwrap();
+        let saves = SaveManager::new(directory.path().join("saves"));
+        let tile_atlas = tile::test_atlas(8);
+
+        assert!(saves.list_saves().is_empty());
+
+        let mut city = flat_city(&tile_atlas);
+        city.funds = 1234.0;
+        saves.save(&city, 3).unwrap();
+
+        let listed = saves.list_saves();
+        assert_eq!(listed, vec![saves.slot_path(3)]);
+
+        let mut loaded = flat_city(&tile_atlas);
+        saves.load_path(&mut loaded, &listed[0], &tile_atlas).unwrap();
+        assert_eq!(loaded.funds, 1234.0);
+    }
+
+    #[test]
+    fn autosaves_are_listed_and_loaded_with_their_delta() {
+        let directory = TempDir::new("saves").unwrap();
+        let mut saves = SaveManager::new(directory.path().clone());
+        let tile_atlas = tile::test_atlas(8);
+
+        let mut city = flat_city(&tile_atlas);
+        assert_eq!(saves.autosave(&city, 1).unwrap(), saves.autosave_path(1));
+
+        city.funds = 4321.0;
+        {
+            let &(ref mut tile, _, _) = city.map.mut_tile(0);
+            *tile = tile_atlas.find(&"road").unwrap().clone();
+        }
+        assert_eq!(saves.autosave(&city, 1).unwrap(), saves.autosave_delta_path(1));
+
+        let listed = saves.list_saves();
+        assert_eq!(listed, vec![saves.autosave_path(1)]);
+
+        let mut loaded = flat_city(&tile_atlas);
+        saves.load_path(&mut loaded, &listed[0], &tile_atlas).unwrap();
+        assert_eq!(loaded.funds, 4321.0);
+        let (ref tile, _, _) = *loaded.map.tile(0);
+        assert!(tile.tile_type == tile::Road);
+    }
+
+    #[test]
+    fn autosaves_of_another_size_are_saved_in_full() {
+        let directory = TempDir::new("saves").unwrap();
+        let mut saves = SaveManager::new(directory.path().clone());
+        let tile_atlas = tile::test_atlas(8);
+
+        let city = flat_city(&tile_atlas);
+        saves.autosave(&city, 0).unwrap();
+        saves.autosave(&city, 0).unwrap();
+        assert!(saves.autosave_delta_path(0).exists());
+
+        let mut params = map::GenerationParams::new();
+        params.width = 6;
+        params.height = 5;
+        params.flat = true;
+        let mut map = map::Map::empty(8);
+        map.regenerate([4, 3, 2, 1], &params, &tile_atlas);
+        let smaller = City::with_params(map, CityParams::normal());
+
+        //the delta can't be made against the old baseline, so the old delta is replaced by a full save
+        assert_eq!(saves.autosave(&smaller, 0).unwrap(), saves.autosave_path(0));
+        assert!(!saves.autosave_delta_path(0).exists());
+
+        let mut loaded = flat_city(&tile_atlas);
+        saves.load_autosave(&mut loaded, 0, &tile_atlas).unwrap();
+        assert_eq!(loaded.map.size(), (6, 5));
+    }
+
+    #[test]
+    fn missing_saves_are_described() {
+        let directory = TempDir::new("saves").unwrap();
+        let saves = SaveManager::new(directory.path().clone());
+        let tile_atlas = tile::test_atlas(8);
+
+        let mut city = flat_city(&tile_atlas);
+        let error = saves.load(&mut city, 0, &tile_atlas).unwrap_err();
+        assert!(error.as_slice().starts_with("the save does not exist"), "{}", error);
+    }
+}
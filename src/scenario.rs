@@ -0,0 +1,137 @@
+use std::io;
+
+use city::City;
+
+pub static SCENARIO_PATH: &'static str = "media/scenario.txt";
+
+pub enum Objective {
+    //reach the population before the end of the day
+    ReachPopulation(f64, uint),
+    //keep the funds above zero until the day
+    KeepFunds(uint),
+    //have this many tiles with the atlas name, where buildings only count once
+    BuildTiles(String, uint)
+}
+
+#[deriving(Clone, PartialEq)]
+pub enum Progress {
+    InProgress,
+    Completed,
+    Failed
+}
+
+impl Objective {
+    fn evaluate(&self, city: &City, progress: Progress) -> Progress {
+        match *self {
+            ReachPopulation(target, day) => if progress == Completed || city.population >= target {
+                Completed
+            } else if city.day > day {
+                Failed
+            } else {
+                InProgress
+            },
+            KeepFunds(day) => if progress == Failed || city.funds < 0.0 {
+                Failed
+            } else if city.day >= day {
+                Completed
+            } else {
+                InProgress
+            },
+            //buildings can be demolished again, so this one is never settled
+            BuildTiles(ref name, count) => if count_tiles(city, name.as_slice()) >= count {
+                Completed
+            } else {
+                InProgress
+            }
+        }
+    }
+
+    fn describe(&self, city: &City) -> String {
+        match *self {
+            ReachPopulation(target, day) => format!("Population {:.0}/{:.0} by day {}", city.population, target, day),
+            KeepFunds(day) => format!("Stay out of debt until day {}", day),
+            BuildTiles(ref name, count) => format!("Build {} {}: {}/{}", count, name, count_tiles(city, name.as_slice()), count)
+        }
+    }
+}
+
+pub struct Scenario {
+    objectives: Vec<(Objective, Progress)>
+}
+
+impl Scenario {
+    //each line is on the form "population = 1000 by 365", "positive_funds = 180" or "build road = 50",
+    //and lines that can't be read are skipped
+    pub fn load(path: &Path) -> io::IoResult<Scenario> {
+        let mut file = io::BufferedReader::new(try!(io::File::open(path)));
+        let mut objectives = Vec::new();
+
+        for line in file.lines() {
+            let line = try!(line);
+            match parse_objective(line.as_slice()) {
+                Some(objective) => objectives.push((objective, InProgress)),
+                None => {}
+            }
+        }
+
+        Ok(Scenario {
+            objectives: objectives
+        })
+    }
+
+    pub fn restart(&mut self) {
+        for &(_, ref mut progress) in self.objectives.mut_iter() {
+            *progress = InProgress;
+        }
+    }
+
+    pub fn update(&mut self, city: &City) {
+        for &(ref objective, ref mut progress) in self.objectives.mut_iter() {
+            *progress = objective.evaluate(city, progress.clone());
+        }
+    }
+
+    pub fn is_won(&self) -> bool {
+        !self.objectives.is_empty() && self.objectives.iter().all(|&(_, ref progress)| *progress == Completed)
+    }
+
+    pub fn describe(&self, city: &City) -> Vec<String> {
+        self.objectives.iter().map(|&(ref objective, ref progress)| {
+            let line = objective.describe(city);
+            match *progress {
+                InProgress => line,
+                Completed => format!("{} (done)", line),
+                Failed => format!("{} (failed)", line)
+            }
+        }).collect()
+    }
+}
+
+fn parse_objective(line: &str) -> Option<Objective> {
+    let parts: Vec<&str> = line.split('=').map(|part| part.trim()).collect();
+    if parts.len() != 2 {
+        return None;
+    }
+
+    let words: Vec<&str> = parts[0].words().collect();
+    let values: Vec<&str> = parts[1].words().collect();
+
+    match (words.as_slice(), values.as_slice()) {
+        (["population"], [target, "by", day]) => match (from_str::<f64>(target), from_str::<uint>(day)) {
+            (Some(target), Some(day)) => Some(ReachPopulation(target, day)),
+            _ => None
+        },
+        (["positive_funds"], [day]) => from_str::<uint>(day).map(|day| KeepFunds(day)),
+        (["build", name], [count]) => from_str::<uint>(count).map(|count| BuildTiles(name.to_string(), count)),
+        _ => None
+    }
+}
+
+fn count_tiles(city: &City, name: &str) -> uint {
+    let (width, height) = city.map.size();
+
+    range(0, width * height).filter(|&index| {
+        let &(ref tile, _, _) = city.map.tile(index);
+        tile.anchor.is_none() && tile.tile_type.atlas_name() == name
+    }).count()
+}
@@ -0,0 +1,78 @@
+use std::io;
+
+pub static SETTINGS_PATH: &'static str = "media/settings.cfg";
+
+pub struct Settings {
+    pub window_width: uint,
+    pub window_height: uint,
+    pub theme: String,
+    pub animate_tiles: bool,
+    pub sync_animations: bool,
+    pub edge_scrolling: bool,
+    pub pixel_zoom: bool
+}
+
+impl Settings {
+    pub fn new() -> Settings {
+        Settings {
+            window_width: 800,
+            window_height: 600,
+            theme: "classic".to_string(),
+            animate_tiles: true,
+            sync_animations: false,
+            edge_scrolling: true,
+            pixel_zoom: false
+        }
+    }
+
+    //each line is on the form "name = value", and unknown names or unreadable values keep their defaults
+    pub fn load(path: &Path) -> io::IoResult<Settings> {
+        let mut settings = Settings::new();
+        let mut file = io::BufferedReader::new(try!(io::File::open(path)));
+
+        for line in file.lines() {
+            let line = try!(line);
+            let parts: Vec<&str> = line.as_slice().split('=').map(|part| part.trim()).collect();
+            if parts.len() != 2 {
+                continue;
+            }
+
+            settings.set(parts[0], parts[1]);
+        }
+
+        Ok(settings)
+    }
+
+    pub fn save(&self, path: &Path) -> io::IoResult<()> {
+        let mut file = io::BufferedWriter::new(try!(io::File::create(path)));
+
+        try!(writeln!(file, "window_width = {}", self.window_width));
+        try!(writeln!(file, "window_height = {}", self.window_height));
+        try!(writeln!(file, "theme = {}", self.theme));
+        try!(writeln!(file, "animate_tiles = {}", self.animate_tiles));
+        try!(writeln!(file, "sync_animations = {}", self.sync_animations));
+        try!(writeln!(file, "edge_scrolling = {}", self.edge_scrolling));
+        try!(writeln!(file, "pixel_zoom = {}", self.pixel_zoom));
+
+        file.flush()
+    }
+
+    fn set(&mut self, name: &str, value: &str) {
+        match name {
+            "window_width" => match from_str::<uint>(value) {
+                Some(width) if width > 0 => self.window_width = width,
+                _ => {}
+            },
+            "window_height" => match from_str::<uint>(value) {
+                Some(height) if height > 0 => self.window_height = height,
+                _ => {}
+            },
+            "theme" => self.theme = value.to_string(),
+            "animate_tiles" => self.animate_tiles = from_str::<bool>(value).unwrap_or(self.animate_tiles),
+            "sync_animations" => self.sync_animations = from_str::<bool>(value).unwrap_or(self.sync_animations),
+            "edge_scrolling" => self.edge_scrolling = from_str::<bool>(value).unwrap_or(self.edge_scrolling),
+            "pixel_zoom" => self.pixel_zoom = from_str::<bool>(value).unwrap_or(self.pixel_zoom),
+            _ => {}
+        }
+    }
+}
@@ -9,10 +9,13 @@ use rsfml::system::vector2::{ToVec, Vector2f, Vector2i};
 use game;
 use edit_state;
 use gui;
+use city;
+use records;
 
 pub struct StartState<'s> {
     view: Rc<RefCell<rsfml::graphics::View>>,
-    menu: gui::Gui<'s, 'static, &'static str>
+    menu: gui::Gui<'s, 'static, &'static str>,
+    records_text: gui::Gui<'s, 'static, ()>
 }
 
 impl<'s> StartState<'s> {
@@ -28,21 +31,50 @@ impl<'s> StartState<'s> {
         let mut menu = gui::Gui::new(
             Vector2f::new(192.0, 32.0), 4, false,
             game.stylesheets.find(&"button").unwrap().clone(),
-            vec![("New Game", "new_game")]
+            vec![
+                ("New Game (Easy)", "new_game_easy"),
+                ("New Game", "new_game"),
+                ("New Game (Hard)", "new_game_hard")
+            ]
         );
 
         menu.transform.set_position(&center);
         menu.transform.set_origin(&Vector2f::new(96.0, 16.0));
         menu.show();
 
+        let mut records_text = gui::Gui::empty(
+            Vector2f::new(192.0, 16.0), 0, false,
+            game.stylesheets.find(&"text").unwrap().clone()
+        );
+
+        match records::Records::load(&Path::new(records::RECORDS_PATH)) {
+            Ok(records) => {
+                records_text.set_entries(vec![(format!("Best: pop {:.0}, funds ${:.0}", records.population, records.funds), ())]);
+                records_text.transform.set_position(&center.add(&Vector2f::new(0.0, menu.measure().y - 8.0)));
+                records_text.transform.set_origin(&Vector2f::new(96.0, 0.0));
+                records_text.show();
+            },
+            Err(_) => {}
+        }
+
         Some(StartState {
             view: Rc::new(RefCell::new(view)),
-            menu: menu
+            menu: menu,
+            records_text: records_text
         })
     }
 
-    fn load_game(&self, game: &mut game::Game) {
-        let state = edit_state::EditState::new(game).expect("could not load game");
+    fn start(&self, game: &mut game::Game, action: Option<&'static str>) {
+        match action {
+            Some("new_game_easy") => self.load_game(game, city::CityParams::easy()),
+            Some("new_game") => self.load_game(game, city::CityParams::normal()),
+            Some("new_game_hard") => self.load_game(game, city::CityParams::hard()),
+            _ => {}
+        }
+    }
+
+    fn load_game(&self, game: &mut game::Game, params: city::CityParams) {
+        let state = edit_state::EditState::new(game, params).expect("could not load game");
         game.push_state(box state as Box<game::GameState>);
     }
 }
@@ -53,6 +85,7 @@ impl<'s> game::GameState for StartState<'s> {
         game.window.clear(&rsfml::graphics::Color::black());
         game.window.draw(&game.background);
         game.window.draw(&self.menu);
+        game.window.draw(&self.records_text);
     }
 
     fn update(&mut self, _dt: f32) {
@@ -64,23 +97,25 @@ impl<'s> game::GameState for StartState<'s> {
 
         loop {
             match game.window.poll_event() {
-                Closed => game.window.close(),
+                Closed => game.request_quit(),
                 Resized {width, height} => {
                     self.view.borrow_mut().set_size(&Vector2f::new(width as f32, height as f32));
                     let background_size = game.background.get_texture().unwrap().borrow().get_size();
                     game.background.set_position(&game.window.map_pixel_to_coords(&Vector2i::new(0, 0), self.view.borrow().deref()));
                     game.background.set_scale(&Vector2f::new(width as f32 / background_size.x as f32, height as f32 / background_size.y as f32));
                 },
-                KeyPressed {code: rsfml::window::keyboard::Escape, ..} => game.window.close(),
+                KeyPressed {code, ..} if game.key_bindings.is_bound("quit", code) => game.request_quit(),
+                KeyPressed {code, ..} => {
+                    let action = self.menu.handle_key(code).map(|&name| name);
+                    self.start(game, action);
+                },
                 MouseMoved {..} => {
                     let index = self.menu.get_entry(&mouse_pos);
-                    self.menu.highlight(index);
+                    self.menu.focus(index);
                 },
                 MouseButtonReleased {button: mouse::MouseLeft, ..} => {
-                    match self.menu.activate_at(&mouse_pos) {
-                        Some(&"new_game") => self.load_game(game),
-                        _ => {}
-                    }
+                    let action = self.menu.activate_at(&mouse_pos).map(|&name| name);
+                    self.start(game, action);
                 },
                 NoEvent => break,
                 _ => {}
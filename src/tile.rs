@@ -1,15 +1,20 @@
-use std::rand::{Rng, task_rng};
+use std::rand::Rng;
 use std::fmt;
 use std::rc::Rc;
 use std::cell::RefCell;
+use std::io;
+use std::collections::HashMap;
 
 use rsfml;
-use rsfml::graphics::{RenderWindow, IntRect};
+use rsfml::graphics::{RenderWindow, IntRect, ConvexShape, Color};
 use rsfml::graphics::rc::Sprite;
 use rsfml::system::vector2::Vector2f;
 
 pub type TextureRc = Rc<RefCell<rsfml::graphics::Texture>>;
 
+//number of commuters a road tile can carry before it's fully congested
+pub static ROAD_CAPACITY: f32 = 40.0;
+
 #[deriving(Clone)]
 pub struct Animation {
     pub start_frame: uint,
@@ -73,21 +78,40 @@ impl AnimationHandler {
         }
 
         let duration = self.animations[self.current_anim].duration;
+        let length = self.animations[self.current_anim].get_length();
+        let total_duration = duration * length as f32;
+        if !(total_duration > 0.0) {
+            return
+        }
+
+        //the time is kept within one loop of the animation, so it never grows large enough to lose precision
+        let time = (self.time + dt) % total_duration;
 
-        let frame = ((self.time + dt) / duration) as i32;
-        if frame > (self.time / duration) as i32 {
-            let frame = frame % self.animations[self.current_anim].get_length() as i32;
+        let frame = (time / duration) as i32 % length as i32;
+        if frame != (self.time / duration) as i32 % length as i32 {
             let (width, height) = self.frame_size;
             let width = width as i32;
             let height = height as i32;
-            self.bounds = IntRect::new(width * frame as i32, height * self.current_anim as i32, width, height);
+            self.bounds = IntRect::new(width * frame, height * self.current_anim as i32, width, height);
         }
 
-        self.time += dt;
+        self.time = time;
+    }
 
-        if dt > duration * self.animations[self.current_anim].get_length() as f32 {
-            self.time = 0.0
-        }
+    pub fn animation_count(&self) -> uint {
+        self.animations.len()
+    }
+
+    //true if the current animation never changes frame
+    pub fn is_static(&self) -> bool {
+        self.current_anim >= self.animations.len() || self.animations[self.current_anim].get_length() == 1
+    }
+
+    //the smallest texture size that has room for every frame of every animation
+    pub fn required_size(&self) -> (uint, uint) {
+        let (width, height) = self.frame_size;
+        let frames = self.animations.iter().map(|animation| animation.end_frame + 1).max().unwrap_or(1);
+        (width * frames, height * self.animations.len())
     }
 
     pub fn change_animation(&mut self, new_animation: uint) {
@@ -100,7 +124,85 @@ impl AnimationHandler {
     }
 }
 
-#[deriving(Clone)]
+#[deriving(Clone, PartialEq)]
+pub enum Direction {
+    North,
+    East,
+    South,
+    West
+}
+
+impl Direction {
+    pub fn cycle(direction: Option<Direction>) -> Option<Direction> {
+        match direction {
+            None => Some(North),
+            Some(North) => Some(East),
+            Some(East) => Some(South),
+            Some(South) => Some(West),
+            Some(West) => None
+        }
+    }
+
+    pub fn save_id(direction: Option<Direction>) -> u8 {
+        match direction {
+            None => 0,
+            Some(North) => 1,
+            Some(East) => 2,
+            Some(South) => 3,
+            Some(West) => 4
+        }
+    }
+
+    pub fn from_save_id(id: u8) -> Option<Direction> {
+        match id {
+            1 => Some(North),
+            2 => Some(East),
+            3 => Some(South),
+            4 => Some(West),
+            _ => None
+        }
+    }
+
+    //the direction as an offset on screen, where the map is drawn isometrically
+    fn screen_offset(&self) -> Vector2f {
+        match *self {
+            North => Vector2f::new(1.0, -0.5),
+            East => Vector2f::new(1.0, 0.5),
+            South => Vector2f::new(-1.0, 0.5),
+            West => Vector2f::new(-1.0, -0.5)
+        }
+    }
+}
+
+impl fmt::Show for Direction {
+    fn fmt(&self, buf: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            North => write!(buf, "North"),
+            East => write!(buf, "East"),
+            South => write!(buf, "South"),
+            West => write!(buf, "West")
+        }
+    }
+}
+
+#[deriving(Clone, PartialEq)]
+pub enum Density {
+    LowDensity,
+    MediumDensity,
+    HighDensity
+}
+
+impl fmt::Show for Density {
+    fn fmt(&self, buf: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LowDensity => write!(buf, "Low"),
+            MediumDensity => write!(buf, "Medium"),
+            HighDensity => write!(buf, "High")
+        }
+    }
+}
+
+#[deriving(Clone, PartialEq)]
 pub enum TileType {
     Void,
     Grass,
@@ -109,11 +211,17 @@ pub enum TileType {
     Residential {
         pub population: f64,
         pub max_pop_per_level: uint,
+        pub density: Density,
         max_levels: uint
     },
     Commercial {
         pub population: f64,
         pub max_pop_per_level: uint,
+        pub received_goods: u32,
+        //goods delivered beyond what was sold, to sell on the following days
+        pub goods_buffer: u32,
+        //received_goods means nothing until the shop has been through a day, and it isn't saved
+        pub supplied: bool,
         max_levels: uint
     },
     Industrial {
@@ -123,14 +231,16 @@ pub enum TileType {
         pub stored_goods: u32,
         max_levels: uint
     },
-    Road
+    Road,
+    Airport
 }
 
 impl TileType {
-    pub fn residential(max_pop_per_level: uint, max_levels: uint) -> TileType {
+    pub fn residential(density: Density, max_pop_per_level: uint, max_levels: uint) -> TileType {
         Residential {
             population: 0.0,
             max_pop_per_level: max_pop_per_level,
+            density: density,
             max_levels: max_levels
         }
     }
@@ -139,7 +249,10 @@ impl TileType {
         Commercial {
             population: 0.0,
             max_pop_per_level: max_pop_per_level,
-            max_levels: max_levels
+            max_levels: max_levels,
+            received_goods: 0,
+            goods_buffer: 0,
+            supplied: false
         }
     }
 
@@ -153,6 +266,103 @@ impl TileType {
         }
     }
 
+    pub fn save_id(&self) -> u8 {
+        match *self {
+            Void => 0,
+            Grass => 1,
+            Forest => 2,
+            Water => 3,
+            Residential {density: LowDensity, ..} => 9,
+            Residential {density: MediumDensity, ..} => 4,
+            Residential {density: HighDensity, ..} => 10,
+            Commercial {..} => 5,
+            Industrial {..} => 6,
+            Road => 7,
+            Airport => 8
+        }
+    }
+
+    pub fn atlas_name(&self) -> &'static str {
+        match *self {
+            Void => "void",
+            Grass => "grass",
+            Forest => "forest",
+            Water => "water",
+            Residential {density: LowDensity, ..} => "residential_low",
+            Residential {density: MediumDensity, ..} => "residential",
+            Residential {density: HighDensity, ..} => "residential_high",
+            Commercial {..} => "commercial",
+            Industrial {..} => "industrial",
+            Road => "road",
+            Airport => "airport"
+        }
+    }
+
+    pub fn atlas_name_for_save_id(id: u8) -> Option<&'static str> {
+        let types = [
+            Void, Grass, Forest, Water,
+            TileType::residential(LowDensity, 0, 0), TileType::residential(MediumDensity, 0, 0), TileType::residential(HighDensity, 0, 0),
+            TileType::commercial(0, 0), TileType::industrial(0, 0),
+            Road, Airport
+        ];
+
+        types.iter().find(|tile_type| tile_type.save_id() == id).map(|tile_type| tile_type.atlas_name())
+    }
+
+    //decides if this type of tile may replace an existing tile when placed
+    pub fn can_build_over(&self, existing: &TileType) -> bool {
+        match *self {
+            Void => false,
+            //flattening clears anything but water and cliffs
+            Grass => !existing.similar_to(&Water) && !existing.similar_to(&Void),
+            //everything else needs cleared land, so buildings have to be demolished first
+            Forest | Water | Residential {..} | Commercial {..} | Industrial {..} | Road | Airport => match *existing {
+                Grass => true,
+                _ => false
+            }
+        }
+    }
+
+    //a shop that sold nothing during the last day, but not one that hasn't had a chance yet
+    pub fn lacks_goods(&self) -> bool {
+        match *self {
+            Commercial {received_goods: 0, supplied: true, ..} => true,
+            _ => false
+        }
+    }
+
+    //building on land gets pricier as the city grows, but clearing it doesn't
+    pub fn follows_land_cost(&self) -> bool {
+        match *self {
+            Road | Residential {..} | Commercial {..} | Industrial {..} | Airport => true,
+            _ => false
+        }
+    }
+
+    pub fn can_demolish(&self) -> bool {
+        match *self {
+            Road | Residential {..} | Commercial {..} | Industrial {..} | Airport => true,
+            _ => false
+        }
+    }
+
+    //the size of the building in tiles, as (width, height)
+    pub fn footprint(&self) -> (uint, uint) {
+        match *self {
+            Airport => (2, 2),
+            _ => (1, 1)
+        }
+    }
+
+    //a tile type that has to be next to the building when it's placed
+    pub fn required_neighbor(&self) -> Option<TileType> {
+        match *self {
+            //the planes are useless without a road to bring the passengers in
+            Airport => Some(Road),
+            _ => None
+        }
+    }
+
     pub fn similar_to(&self, other: &TileType) -> bool {
         match (self, other) {
             (&Void, &Void) => true,
@@ -163,6 +373,7 @@ impl TileType {
             (&Commercial {..}, &Commercial {..}) => true,
             (&Industrial {..}, &Industrial {..}) => true,
             (&Road, &Road) => true,
+            (&Airport, &Airport) => true,
             _ => false
         }
     }
@@ -178,7 +389,8 @@ impl fmt::Show for TileType {
             Residential {..} => write!(buf, "Residential Zone"),
             Commercial {..} => write!(buf, "Commercial Zone"),
             Industrial {..} => write!(buf, "Industrial Zone"),
-            Road => write!(buf, "Road")
+            Road => write!(buf, "Road"),
+            Airport => write!(buf, "Airport")
         }
     }
 }
@@ -190,18 +402,41 @@ pub struct Tile {
     pub variant: uint,
     pub regions: Vec<uint>,
     pub cost: uint,
+    //a grown building that everyone has left. it keeps its variant, so it looks the same if they return
+    pub abandoned: bool,
+    //a new zone that waits for a road before anything moves in
+    pub undeveloped: bool,
+    //set by overlays, and mixed with the state and selection colors when drawing
+    pub tint: Option<Color>,
+    pub direction: Option<Direction>,
+    pub traffic: f32,
+    //days since the zone was built or last grew a level
+    pub age_days: u32,
+    //the position of the tile that holds the data for a building that covers more than one tile
+    pub anchor: Option<(uint, uint)>,
     animation_handler: AnimationHandler
 }
 
 impl Tile {
     pub fn new(tile_size: uint, height: uint, texture: TextureRc, animations: Vec<Animation>, tile_type: TileType, cost: uint) -> Tile {
+        let sprite = Sprite::new_with_texture(texture).unwrap();
+        Tile::with_sprite(sprite, tile_size, height, animations, tile_type, cost)
+    }
+
+    //a tile that can be simulated, but not drawn
+    #[cfg(test)]
+    pub fn untextured(tile_size: uint, height: uint, animations: Vec<Animation>, tile_type: TileType, cost: uint) -> Tile {
+        let sprite = Sprite::new().unwrap();
+        Tile::with_sprite(sprite, tile_size, height, animations, tile_type, cost)
+    }
+
+    fn with_sprite(mut sprite: Sprite, tile_size: uint, height: uint, animations: Vec<Animation>, tile_type: TileType, cost: uint) -> Tile {
         let mut animation_handler = AnimationHandler::new_with_size(tile_size * 2, tile_size * height);
         for animation in animations.move_iter() {
             animation_handler.add_animation(animation);
         }
         animation_handler.update(0.0);
 
-        let mut sprite = Sprite::new_with_texture(texture).unwrap();
         sprite.set_origin(&Vector2f::new(0.0, (tile_size * (height - 1)) as f32));
 
         Tile {
@@ -210,29 +445,316 @@ impl Tile {
             variant: 0,
             regions: vec![0],
             cost: cost,
+            abandoned: false,
+            undeveloped: false,
+            tint: None,
+            direction: None,
+            traffic: 0.0,
+            age_days: 0,
+            anchor: None,
             animation_handler: animation_handler
         }
     }
 
-    pub fn draw(&mut self, window: &mut RenderWindow, dt: f32) {
+    pub fn variant_count(&self) -> uint {
+        self.animation_handler.animation_count()
+    }
+
+    pub fn fits_texture(&self) -> bool {
+        let (width, height) = self.animation_handler.required_size();
+        match self.sprite.get_texture() {
+            Some(texture) => {
+                let size = texture.borrow().get_size();
+                size.x as uint >= width && size.y as uint >= height
+            },
+            None => false
+        }
+    }
+
+    //picks the frame to draw, advancing the animation if it's enabled
+    pub fn update_frame(&mut self, dt: f32, animate: bool) {
         self.animation_handler.change_animation(self.variant);
-        self.animation_handler.update(dt);
+        if animate {
+            self.animation_handler.update(dt);
+        }
         self.sprite.set_texture_rect(&self.animation_handler.bounds);
+    }
+
+    pub fn draw(&self, window: &mut RenderWindow) {
         window.draw(&self.sprite);
+        self.draw_direction(window);
     }
 
-    pub fn update(&mut self) {
+    pub fn draw_direction(&self, window: &mut RenderWindow) {
+        match self.direction {
+            Some(ref direction) => self.draw_arrow(window, direction),
+            None => {}
+        }
+    }
+
+    //flat and still, so it can be drawn in a batch below everything else
+    pub fn is_ground(&self) -> bool {
+        let (width, height) = self.animation_handler.frame_size;
+        height * 2 == width && self.animation_handler.is_static()
+    }
+
+    fn draw_arrow(&self, window: &mut RenderWindow, direction: &Direction) {
+        let (width, _) = self.animation_handler.frame_size;
+        let tile_size = (width / 2) as f32;
+        let position = self.sprite.get_position();
+        let center = Vector2f::new(position.x + tile_size, position.y + tile_size * 0.5);
+
+        let forward = direction.screen_offset().mul(&(tile_size * 0.4));
+        let side = Vector2f::new(-forward.y, forward.x).mul(&0.6);
+        let back = center.sub(&forward.mul(&0.5));
+
+        let mut arrow = ConvexShape::new(3).expect("unable to create arrow shape");
+        arrow.set_point(0, &center.add(&forward));
+        arrow.set_point(1, &back.add(&side));
+        arrow.set_point(2, &back.sub(&side));
+        arrow.set_fill_color(&Color::new_RGB(0xff, 0xff, 0xff));
+        window.draw(&arrow);
+    }
+
+    pub fn update<R: Rng>(&mut self, rng: &mut R) -> bool {
         match self.tile_type {
-            Residential {population, max_pop_per_level, max_levels} |
-            Commercial {population, max_pop_per_level, max_levels} |
+            Residential {population, max_pop_per_level, max_levels, ..} |
+            Commercial {population, max_pop_per_level, max_levels, ..} |
             Industrial {population, max_pop_per_level, max_levels, ..}
-            => if population as uint == max_pop_per_level * (self.variant + 1) && self.variant < max_levels {
-                if (0.01f32 / (self.variant + 1) as f32) > task_rng().gen() {
-                    self.variant += 1;
+            => {
+                //grown buildings are left empty when everyone moves out
+                self.abandoned = population == 0.0 && self.variant > 0;
+                self.age_days += 1;
+
+                if !self.abandoned && population as uint == max_pop_per_level * (self.variant + 1) && self.variant < max_levels {
+                    if (0.01f32 / (self.variant + 1) as f32) > rng.gen() {
+                        self.variant += 1;
+                        self.age_days = 0;
+                        return true;
+                    }
+                }
+            },
+            _ => {}
+        }
+
+        false
+    }
+
+    //drops the building one level and returns how many people no longer fit
+    pub fn decay(&mut self) -> f64 {
+        if self.variant == 0 {
+            return 0.0;
+        }
+
+        self.variant -= 1;
+
+        match self.tile_type {
+            Residential {population, max_pop_per_level, ..} |
+            Commercial {population, max_pop_per_level, ..} |
+            Industrial {population, max_pop_per_level, ..}
+            => {
+                let max_pop = (max_pop_per_level * (self.variant + 1)) as f64;
+                if population > max_pop {
+                    self.set_population(max_pop);
+                    population - max_pop
+                } else {
+                    0.0
                 }
             },
+            _ => 0.0
+        }
+    }
+
+    //reads a tile in the layout of the given map version, starting from a copy of the atlas tile with the saved type
+    pub fn read_from<R: Reader>(file: &mut R, version: u16, tile_atlas: &HashMap<&'static str, Tile>) -> io::IoResult<Tile> {
+        let save_id = try!(file.read_u8());
+        let mut tile = match TileType::atlas_name_for_save_id(save_id) {
+            Some(name) => tile_atlas.find(&name).unwrap().clone(),
+            None => return Err(io::IoError {
+                kind: io::OtherIoError,
+                desc: "invalid tile type in map file",
+                detail: Some(format!("found type number {}", save_id))
+            })
+        };
+
+        let tile_type = tile.tile_type.clone();
+        match tile_type {
+            Residential {..} | Commercial {..} => {
+                tile.set_population(try!(file.read_be_f64()));
+            },
+            Industrial {..} => {
+                tile.set_population(try!(file.read_be_f64()));
+                tile.set_production(try!(file.read_be_u32()));
+                tile.set_stored_goods(try!(file.read_be_u32()));
+            },
+            //maps from before version 1 don't have the road direction
+            Road if version >= 1 => tile.direction = Direction::from_save_id(try!(file.read_u8())),
+            _ => {}
+        }
+
+        tile.variant = try!(file.read_be_u32()) as uint;
+
+        let num_regions = try!(file.read_be_u32()) as uint;
+        let mut regions = Vec::new();
+        for _ in range(0u, num_regions) {
+            regions.push(try!(file.read_be_u32()) as uint);
+        }
+        tile.regions = regions;
+
+        //version 1 had no buildings larger than one tile
+        if version >= 2 && tile.tile_type.footprint() != (1, 1) && try!(file.read_u8()) != 0 {
+            let x = try!(file.read_be_u32()) as uint;
+            let y = try!(file.read_be_u32()) as uint;
+            tile.anchor = Some((x, y));
+        }
+
+        match tile.tile_type {
+            Residential {..} | Commercial {..} | Industrial {..} if version >= 4 => {
+                tile.age_days = try!(file.read_be_u32());
+            },
+            _ => {}
+        }
+
+        match tile.tile_type {
+            Commercial {ref mut goods_buffer, ..} if version >= 5 => *goods_buffer = try!(file.read_be_u32()),
+            _ => {}
+        }
+
+        Ok(tile)
+    }
+
+    //writes the tile in the layout of the current map version
+    pub fn write_to<W: Writer>(&self, file: &mut W) -> io::IoResult<()> {
+        try!(file.write_u8(self.tile_type.save_id()));
+
+        match self.tile_type {
+            Residential {population, ..} | Commercial {population, ..} => {
+                try!(file.write_be_f64(population));
+            },
+            Industrial {population, production, stored_goods, ..} => {
+                try!(file.write_be_f64(population));
+                try!(file.write_be_u32(production));
+                try!(file.write_be_u32(stored_goods));
+            },
+            Road => try!(file.write_u8(Direction::save_id(self.direction.clone()))),
             _ => {}
         }
+
+        try!(file.write_be_u32(self.variant as u32));
+        try!(file.write_be_u32(self.regions.len() as u32));
+        for &region in self.regions.iter() {
+            try!(file.write_be_u32(region as u32));
+        }
+
+        if self.tile_type.footprint() != (1, 1) {
+            match self.anchor {
+                Some((x, y)) => {
+                    try!(file.write_u8(1));
+                    try!(file.write_be_u32(x as u32));
+                    try!(file.write_be_u32(y as u32));
+                },
+                None => try!(file.write_u8(0))
+            }
+        }
+
+        match self.tile_type {
+            Residential {..} | Commercial {..} | Industrial {..} => try!(file.write_be_u32(self.age_days)),
+            _ => {}
+        }
+
+        match self.tile_type {
+            Commercial {goods_buffer, ..} => try!(file.write_be_u32(goods_buffer)),
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    pub fn describe(&self, resources: uint) -> Vec<String> {
+        let mut lines = vec![self.tile_type.to_string(), format!("Resources: {}", resources)];
+
+        if self.abandoned {
+            lines.push("Abandoned".to_string());
+        }
+
+        if self.undeveloped {
+            lines.push("Waiting for a road".to_string());
+        }
+
+        match self.tile_type {
+            Residential {population, ref density, ..} => {
+                lines.push(format!("Density: {}", density));
+                lines.push(format!("Level: {}", self.variant + 1));
+                lines.push(format!("Residents: {:.0}", population));
+                lines.push(format!("Age: {} days", self.age_days));
+            },
+            Commercial {population, received_goods, goods_buffer, ..} => {
+                lines.push(format!("Level: {}", self.variant + 1));
+                lines.push(format!("Employees: {:.0}", population));
+                lines.push(format!("Goods sold: {}", received_goods));
+                lines.push(format!("Goods in stock: {}", goods_buffer));
+                lines.push(format!("Age: {} days", self.age_days));
+            },
+            Industrial {population, stored_goods, ..} => {
+                lines.push(format!("Level: {}", self.variant + 1));
+                lines.push(format!("Employees: {:.0}", population));
+                lines.push(format!("Goods in stock: {}", stored_goods));
+                lines.push(format!("Age: {} days", self.age_days));
+            },
+            Road => {
+                lines.push(format!("Traffic: {:.0}", self.traffic));
+                match self.direction {
+                    Some(ref direction) => lines.push(format!("One-way: {}", direction)),
+                    None => {}
+                }
+            },
+            Airport => lines.push("Attracts immigrants when next to a road".to_string()),
+            _ => {}
+        }
+
+        lines
+    }
+
+    pub fn congestion(&self) -> f32 {
+        match self.tile_type {
+            Road => (self.traffic / ROAD_CAPACITY).min(1.0),
+            _ => 0.0
+        }
+    }
+
+    pub fn same_kind(&self, other: &Tile) -> bool {
+        self.tile_type.similar_to(&other.tile_type) && self.variant == other.variant
+    }
+
+    pub fn build_cost(&self) -> uint {
+        self.cost
+    }
+
+    pub fn demolish_refund(&self) -> f64 {
+        match self.tile_type {
+            //grown buildings are worth more when torn down
+            Residential {..} | Commercial {..} | Industrial {..} => self.cost as f64 * 0.1 * (self.variant + 1) as f64,
+            _ => 0.0
+        }
+    }
+
+    pub fn replacement_cost(&self, cost: f64) -> f64 {
+        //abandoned buildings are cheaper to clear
+        if self.abandoned {
+            cost * 0.5
+        } else {
+            cost
+        }
+    }
+
+    pub fn population(&self) -> f64 {
+        match self.tile_type {
+            Residential {population, ..} |
+            Commercial {population, ..} |
+            Industrial {population, ..}
+            => population,
+            _ => 0.0
+        }
     }
 
     pub fn set_population(&mut self, new_population: f64) {
@@ -258,4 +780,84 @@ impl Tile {
             _ => {}
         }
     }
-}
\ No newline at end of file
+}
+
+//the same tiles as the game loads, but without any textures, for the tests
+#[cfg(test)]
+pub fn test_atlas(tile_size: uint) -> HashMap<&'static str, Tile> {
+    let mut tiles = HashMap::new();
+
+    tiles.insert("void", Tile::untextured(tile_size, 1, vec![Animation::new_static()], Void, 0));
+    tiles.insert("grass", Tile::untextured(tile_size, 1, vec![Animation::new_static()], Grass, 50));
+    tiles.insert("demolish", Tile::untextured(tile_size, 1, vec![Animation::new_static()], Grass, 10));
+    tiles.insert("forest", Tile::untextured(tile_size, 1, vec![Animation::new_static()], Forest, 100));
+    tiles.insert("water", Tile::untextured(tile_size, 1, Vec::from_elem(3, Animation::new(0, 3, 0.5)), Water, 0));
+    tiles.insert("residential_low", Tile::untextured(tile_size, 2, Vec::from_elem(6, Animation::new_static()), TileType::residential(LowDensity, 20, 2), 200));
+    tiles.insert("residential", Tile::untextured(tile_size, 2, Vec::from_elem(6, Animation::new_static()), TileType::residential(MediumDensity, 50, 6), 300));
+    tiles.insert("residential_high", Tile::untextured(tile_size, 2, Vec::from_elem(6, Animation::new_static()), TileType::residential(HighDensity, 80, 6), 500));
+    tiles.insert("commercial", Tile::untextured(tile_size, 2, Vec::from_elem(4, Animation::new_static()), TileType::commercial(50, 4), 300));
+    tiles.insert("industrial", Tile::untextured(tile_size, 2, Vec::from_elem(4, Animation::new_static()), TileType::industrial(50, 4), 300));
+    tiles.insert("road", Tile::untextured(tile_size, 1, Vec::from_elem(11, Animation::new_static()), Road, 100));
+    tiles.insert("airport", Tile::untextured(tile_size, 2, vec![Animation::new_static()], Airport, 5000));
+
+    tiles
+}
+
+#[cfg(test)]
+mod test {
+    use super::{AnimationHandler, Animation, TileType, Commercial};
+
+    //four frames of half a second, where each frame is 16 pixels wide
+    fn looping_handler() -> AnimationHandler {
+        let mut handler = AnimationHandler::new_with_size(16, 8);
+        handler.add_animation(Animation::new(0, 3, 0.5));
+        handler
+    }
+
+    #[test]
+    fn time_wraps_after_a_huge_delta() {
+        let mut handler = looping_handler();
+
+        //2^20 loops of two seconds, and 1.25 seconds into the next one, which is exact in an f32
+        handler.update(2097153.25);
+
+        assert!(handler.time < 2.0, "the time grew to {}", handler.time);
+        assert_eq!(handler.time, 1.25);
+        assert_eq!(handler.bounds.left, 2 * 16);
+    }
+
+    #[test]
+    fn frames_keep_advancing_after_a_huge_delta() {
+        let mut handler = looping_handler();
+        handler.update(2097153.25);
+
+        handler.update(0.5);
+        assert_eq!(handler.bounds.left, 3 * 16);
+
+        //past the last frame and back to the first
+        handler.update(0.5);
+        assert_eq!(handler.bounds.left, 0);
+        assert!(handler.time < 2.0);
+    }
+
+    #[test]
+    fn new_shops_dont_lack_goods() {
+        assert!(!TileType::commercial(10, 3).lacks_goods());
+    }
+
+    #[test]
+    fn supplied_shops_lack_goods_when_nothing_was_sold() {
+        let mut shop = TileType::commercial(10, 3);
+        match shop {
+            Commercial {ref mut supplied, ..} => *supplied = true,
+            _ => unreachable!()
+        }
+        assert!(shop.lacks_goods());
+
+        match shop {
+            Commercial {ref mut received_goods, ..} => *received_goods = 2,
+            _ => unreachable!()
+        }
+        assert!(!shop.lacks_goods());
+    }
+}
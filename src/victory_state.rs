@@ -0,0 +1,78 @@
+use std::rc::Rc;
+use std::cell::RefCell;
+
+use rsfml;
+use rsfml::window::event::{Closed, Resized, KeyPressed, MouseButtonReleased, NoEvent};
+use rsfml::system::vector2::{ToVec, Vector2f, Vector2i};
+
+use game;
+use gui;
+
+//shown on top of the city when a scenario is completed, until any key or button is pressed
+pub struct VictoryState<'s> {
+    view: Rc<RefCell<rsfml::graphics::View>>,
+    text: gui::Gui<'s, 'static, ()>
+}
+
+impl<'s> VictoryState<'s> {
+    pub fn new(game: &game::Game, day: uint, population: f64) -> Option<VictoryState<'s>> {
+        let size = game.window.get_size().to_vector2f();
+        let center = size.mul(&0.5f32);
+
+        let view = match rsfml::graphics::View::new_init(&center, &size) {
+            Some(view) => view,
+            None => return None
+        };
+
+        let mut text = gui::Gui::new(
+            Vector2f::new(320.0, 32.0), 4, false,
+            game.stylesheets.find(&"button").unwrap().clone(),
+            vec![
+                ("Scenario complete!".to_string(), ()),
+                (format!("Day {}, population {:.0}", day, population), ()),
+                ("Press any key to keep building".to_string(), ())
+            ]
+        );
+
+        text.transform.set_position(&center);
+        text.transform.set_origin(&Vector2f::new(160.0, 48.0));
+        text.show();
+
+        Some(VictoryState {
+            view: Rc::new(RefCell::new(view)),
+            text: text
+        })
+    }
+}
+
+impl<'s> game::GameState for VictoryState<'s> {
+    fn draw(&mut self, _dt: f32, game: &mut game::Game) {
+        game.window.set_view(self.view.clone());
+        game.window.draw(&game.background);
+        game.window.draw(&self.text);
+    }
+
+    fn update(&mut self, _dt: f32) {
+
+    }
+
+    fn handle_input(&mut self, game: &mut game::Game) {
+        loop {
+            match game.window.poll_event() {
+                Closed => game.request_quit(),
+                Resized {width, height} => {
+                    self.view.borrow_mut().set_size(&Vector2f::new(width as f32, height as f32));
+                    let background_size = game.background.get_texture().unwrap().borrow().get_size();
+                    game.background.set_position(&game.window.map_pixel_to_coords(&Vector2i::new(0, 0), self.view.borrow().deref()));
+                    game.background.set_scale(&Vector2f::new(width as f32 / background_size.x as f32, height as f32 / background_size.y as f32));
+                },
+                KeyPressed {..} | MouseButtonReleased {..} => {
+                    game.pop_state();
+                    break;
+                },
+                NoEvent => break,
+                _ => {}
+            }
+        }
+    }
+}